@@ -0,0 +1,205 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use cpp_core::{Ptr, StaticUpcast};
+use futures_util::StreamExt;
+use qt_core::{slot, ConnectionType, QBox, QCoreApplication, QObject, SignalNoArgs, SlotNoArgs};
+use qt_gui::{q_palette::ColorRole, QGuiApplication};
+use zbus::{Connection, Proxy};
+use zvariant::{OwnedValue, Value};
+
+// The portal's color-scheme: 0 for no preference, 1 for dark, 2 for light
+static DESKTOP_SCHEME: AtomicU32 = AtomicU32::new(0);
+static GNOME_ANIMATIONS_OFF: AtomicBool = AtomicBool::new(false);
+static KDE_ANIMATIONS_INSTANT: AtomicBool = AtomicBool::new(false);
+
+// Each desktop only has its own
+const ANIMATION_SETTINGS: [(&str, &str); 2] = [
+    ("org.gnome.desktop.interface", "enable-animations"),
+    ("org.kde.kdeglobals.KDE", "AnimationDurationFactor"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        }
+    }
+}
+
+// Without a preference from the desktop, it's dark if the Qt palette's window color is
+pub unsafe fn current() -> ColorScheme {
+    match DESKTOP_SCHEME.load(Ordering::Relaxed) {
+        1 => ColorScheme::Dark,
+        2 => ColorScheme::Light,
+        _ => {
+            let window = QGuiApplication::palette().color_1a(ColorRole::Window);
+
+            if window.lightness() < 128 {
+                ColorScheme::Dark
+            } else {
+                ColorScheme::Light
+            }
+        }
+    }
+}
+
+pub fn desktop_prefers_reduced_motion() -> bool {
+    GNOME_ANIMATIONS_OFF.load(Ordering::Relaxed) || KDE_ANIMATIONS_INSTANT.load(Ordering::Relaxed)
+}
+
+// Without a settings portal, only the Qt palette is used
+pub async fn watch_desktop(on_change: impl Fn() + Send + 'static) {
+    if let Err(err) = follow_portal(on_change).await {
+        eprintln!("Could not read the desktop's color scheme: {err}");
+    }
+}
+
+async fn follow_portal(on_change: impl Fn()) -> zbus::Result<()> {
+    let connection = Connection::session().await?;
+    let proxy = Proxy::new(
+        &connection,
+        "org.freedesktop.portal.Desktop",
+        "/org/freedesktop/portal/desktop",
+        "org.freedesktop.portal.Settings",
+    )
+    .await?;
+
+    // Subscribed to first, so a change right after reading it isn't missed
+    let mut changes = proxy.receive_signal("SettingChanged").await?;
+
+    let value: OwnedValue = proxy
+        .call("Read", &("org.freedesktop.appearance", "color-scheme"))
+        .await?;
+
+    DESKTOP_SCHEME.store(scheme_value(&value), Ordering::Relaxed);
+
+    for (namespace, key) in ANIMATION_SETTINGS {
+        let value: zbus::Result<OwnedValue> = proxy.call("Read", &(namespace, key)).await;
+
+        if let Ok(value) = value {
+            store_animation_setting(namespace, key, &value);
+        }
+    }
+
+    on_change();
+
+    while let Some(message) = changes.next().await {
+        let (namespace, key, value): (String, String, OwnedValue) = message.body()?;
+
+        if namespace == "org.freedesktop.appearance" && key == "color-scheme" {
+            DESKTOP_SCHEME.store(scheme_value(&value), Ordering::Relaxed);
+            on_change();
+        } else if store_animation_setting(&namespace, &key, &value) {
+            on_change();
+        }
+    }
+
+    Ok(())
+}
+
+// Read hands the value back wrapped in another variant
+fn unwrap_value<'a, 'b>(value: &'a Value<'b>) -> &'a Value<'b> {
+    match value {
+        Value::Value(value) => unwrap_value(value),
+        value => value,
+    }
+}
+
+fn scheme_value(value: &Value) -> u32 {
+    match unwrap_value(value) {
+        Value::U32(value) => *value,
+        _ => 0,
+    }
+}
+
+// False if it isn't one of ANIMATION_SETTINGS
+fn store_animation_setting(namespace: &str, key: &str, value: &Value) -> bool {
+    match (namespace, key, unwrap_value(value)) {
+        ("org.gnome.desktop.interface", "enable-animations", Value::Bool(enabled)) => {
+            GNOME_ANIMATIONS_OFF.store(!*enabled, Ordering::Relaxed);
+        }
+        // KDE's portal hands kdeglobals entries over as strings
+        ("org.kde.kdeglobals.KDE", "AnimationDurationFactor", Value::Str(factor)) => {
+            KDE_ANIMATIONS_INSTANT.store(
+                factor.as_str().trim().parse::<f64>() == Ok(0.0),
+                Ordering::Relaxed,
+            );
+        }
+        ("org.kde.kdeglobals.KDE", "AnimationDurationFactor", Value::F64(factor)) => {
+            KDE_ANIMATIONS_INSTANT.store(*factor == 0.0, Ordering::Relaxed);
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+// Follows both the portal and the Qt palette
+pub struct SchemeWatcher {
+    // Emitted from the portal's task, and handled on the Qt thread
+    changed: QBox<SignalNoArgs>,
+    qobject: QBox<QObject>,
+    scheme: Cell<ColorScheme>,
+    reduced_motion: Cell<bool>,
+    on_change: Box<dyn Fn()>,
+}
+
+impl StaticUpcast<QObject> for SchemeWatcher {
+    unsafe fn static_upcast(ptr: Ptr<Self>) -> Ptr<QObject> {
+        ptr.qobject.as_ptr().static_upcast()
+    }
+}
+
+impl SchemeWatcher {
+    pub fn new(on_change: impl Fn() + 'static) -> Rc<SchemeWatcher> {
+        unsafe {
+            let this = Rc::new(Self {
+                changed: SignalNoArgs::new(),
+                qobject: QObject::new_0a(),
+                scheme: Cell::new(current()),
+                reduced_motion: Cell::new(desktop_prefers_reduced_motion()),
+                on_change: Box::new(on_change),
+            });
+
+            this.changed
+                .connect_with_type(ConnectionType::QueuedConnection, &this.slot_on_changed());
+
+            let app: Ptr<QGuiApplication> = QCoreApplication::instance().as_ptr().dynamic_cast();
+            app.palette_changed().connect(&this.slot_on_changed());
+
+            this
+        }
+    }
+
+    // For watch_desktop, which calls it from its own thread
+    pub unsafe fn notifier(&self) -> impl Fn() + Send + 'static {
+        let changed = self
+            .changed
+            .as_raw_ref()
+            .expect("could not get a reference to the color scheme signal");
+
+        move || changed.emit()
+    }
+
+    #[slot(SlotNoArgs)]
+    unsafe fn on_changed(self: &Rc<Self>) {
+        let scheme = current();
+        let reduced_motion = desktop_prefers_reduced_motion();
+
+        let scheme_changed = self.scheme.replace(scheme) != scheme;
+        let reduced_motion_changed = self.reduced_motion.replace(reduced_motion) != reduced_motion;
+
+        if scheme_changed || reduced_motion_changed {
+            (self.on_change)();
+        }
+    }
+}
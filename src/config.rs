@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use cpp_core::CppBox;
+use qt_core::{qs, QStringList, QVariant};
+use serde::{Deserialize, Serialize};
+use toml_edit::Document;
+
+use crate::file_watcher::FileWatcher;
+use crate::hooks::{self, Hook};
+use crate::rules::{self, Rule};
+use crate::settings::{config_dir, Durations, Setting, Settings, SETTINGS};
+
+/// Every key is optional, and unknown keys are an error so typos don't go unnoticed.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    theme: String,
+    /// The screen's name, empty for the primary one
+    screen: String,
+    position: String,
+    do_not_disturb: bool,
+    max_notifications: i32,
+    group_notifications: bool,
+    duplicate_window: i32,
+    /// auto, on or off
+    reduced_motion: String,
+    /// "relative", or a Qt date format
+    time_format: String,
+    quirks: Vec<String>,
+    rate_limits: Vec<String>,
+    /// Tables have to come after plain values when it's written out
+    durations: Durations,
+    #[serde(rename = "rule", skip_serializing)]
+    rules: Vec<Rule>,
+    #[serde(rename = "hook", skip_serializing)]
+    hooks: Vec<Hook>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            theme: String::from("default"),
+            screen: String::new(),
+            position: String::from("top-left"),
+            do_not_disturb: false,
+            max_notifications: 5,
+            group_notifications: true,
+            duplicate_window: 10000,
+            reduced_motion: String::from("auto"),
+            time_format: String::from("relative"),
+            quirks: vec![String::from("discord:off_by_one_id")],
+            rate_limits: vec![String::from("*:10/1")],
+            durations: Durations::default(),
+            rules: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// What's currently set, so it can be written out when migrating from QSettings.
+    fn from_settings(settings: &Settings) -> Config {
+        Config {
+            theme: settings.theme.name.to_string(),
+            screen: settings.screen.name.to_string(),
+            position: settings.position.name().to_string(),
+            do_not_disturb: settings.do_not_disturb.value,
+            max_notifications: settings.max_notifications.value,
+            group_notifications: settings.group_notifications.value,
+            duplicate_window: settings.duplicate_window.value,
+            reduced_motion: settings.reduced_motion.name.to_string(),
+            time_format: settings.time_format.format.to_string(),
+            quirks: settings.quirks.entries(),
+            rate_limits: settings.rate_limits.entries(),
+            durations: settings.durations.clone(),
+            rules: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+
+    unsafe fn apply(self) {
+        let settings = &mut SETTINGS;
+
+        settings
+            .theme
+            .set(QVariant::from_q_string(&qs(&self.theme)));
+        settings
+            .screen
+            .set(QVariant::from_q_string(&qs(&self.screen)));
+        settings
+            .position
+            .set(QVariant::from_q_string(&qs(&self.position)));
+        settings
+            .do_not_disturb
+            .set(QVariant::from_bool(self.do_not_disturb));
+        settings
+            .max_notifications
+            .set(QVariant::from_int(self.max_notifications));
+        settings
+            .group_notifications
+            .set(QVariant::from_bool(self.group_notifications));
+        settings
+            .duplicate_window
+            .set(QVariant::from_int(self.duplicate_window));
+        settings
+            .reduced_motion
+            .set(QVariant::from_q_string(&qs(&self.reduced_motion)));
+        settings
+            .time_format
+            .set(QVariant::from_q_string(&qs(&self.time_format)));
+        settings
+            .quirks
+            .set(QVariant::from_q_string_list(&string_list(&self.quirks)));
+        settings
+            .rate_limits
+            .set(QVariant::from_q_string_list(&string_list(
+                &self.rate_limits,
+            )));
+        settings.durations = self.durations;
+
+        rules::set_rules(self.rules);
+        hooks::set_hooks(self.hooks);
+    }
+}
+
+unsafe fn string_list(entries: &[String]) -> CppBox<QStringList> {
+    let list = QStringList::new();
+
+    for entry in entries {
+        list.append_q_string(&qs(entry));
+    }
+
+    list
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Writes config.toml from what was in QSettings, the first time krustyfy runs with it.
+pub unsafe fn migrate() {
+    let path = config_path();
+
+    if path.exists() {
+        return;
+    }
+
+    let mut contents = String::from(
+        "# Moved over from krustyfy.conf. Changes to this file are applied right away.\n\n",
+    );
+
+    match toml::to_string(&Config::from_settings(&SETTINGS)) {
+        Ok(config) => contents.push_str(&config),
+        Err(err) => {
+            eprintln!("Could not write {}: {}", path.display(), err);
+            return;
+        }
+    }
+
+    if let Err(err) = fs::create_dir_all(config_dir()).and_then(|_| fs::write(&path, contents)) {
+        eprintln!("Could not write {}: {}", path.display(), err);
+    }
+}
+
+/// If config.toml is broken, whatever was already in effect is kept.
+pub unsafe fn load_config() {
+    let path = config_path();
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("Could not read {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    match toml::from_str::<Config>(&contents) {
+        Ok(config) => config.apply(),
+        Err(err) => eprintln!("Could not load {}: {}", path.display(), err),
+    }
+}
+
+/// Applies config.toml again every time it changes, then calls `on_reload`.
+pub fn watch_config(on_reload: impl Fn() + 'static) -> Rc<FileWatcher> {
+    FileWatcher::new(config_path(), move || unsafe {
+        load_config();
+        on_reload();
+    })
+}
+
+/// Leaves the rest of config.toml, comments included, alone.
+pub fn save_value(key: &str, value: impl Into<toml_edit::Value>) {
+    let path = config_path();
+
+    let contents = fs::read_to_string(&path).unwrap_or_default();
+
+    let mut document = match contents.parse::<Document>() {
+        Ok(document) => document,
+        Err(err) => {
+            eprintln!("Could not save {} to {}: {}", key, path.display(), err);
+            return;
+        }
+    };
+
+    document[key] = toml_edit::value(value);
+
+    if let Err(err) = fs::write(&path, document.to_string()) {
+        eprintln!("Could not save {} to {}: {}", key, path.display(), err);
+    }
+}
@@ -0,0 +1,88 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use cpp_core::{Ptr, Ref, StaticUpcast};
+use qt_core::{qs, slot, QBox, QFileSystemWatcher, QObject, QString, SlotOfQString};
+
+/// Calls `on_change` whenever the file at `path` is written, created or replaced.
+pub struct FileWatcher {
+    watcher: QBox<QFileSystemWatcher>,
+    path: PathBuf,
+    last_modified: Cell<Option<SystemTime>>,
+    on_change: Box<dyn Fn()>,
+}
+
+impl StaticUpcast<QObject> for FileWatcher {
+    unsafe fn static_upcast(ptr: Ptr<Self>) -> Ptr<QObject> {
+        ptr.watcher.as_ptr().static_upcast()
+    }
+}
+
+impl FileWatcher {
+    pub fn new(path: PathBuf, on_change: impl Fn() + 'static) -> Rc<FileWatcher> {
+        unsafe {
+            let watcher = QFileSystemWatcher::new_0a();
+
+            let last_modified = Cell::new(modified(&path));
+
+            let this = Rc::new(Self {
+                watcher,
+                path,
+                last_modified,
+                on_change: Box::new(on_change),
+            });
+
+            this.init();
+
+            this
+        }
+    }
+
+    unsafe fn init(self: &Rc<Self>) {
+        self.watch();
+
+        self.watcher.file_changed().connect(&self.slot_on_changed());
+        self.watcher
+            .directory_changed()
+            .connect(&self.slot_on_changed());
+    }
+
+    /// Editors usually save by replacing the file, which drops it from the watcher, so the
+    /// directory is watched too and the file is added back whenever it shows up again.
+    unsafe fn watch(self: &Rc<Self>) {
+        if let Some(directory) = self.path.parent() {
+            if directory.exists() {
+                self.watcher.add_path(&qs(directory.to_string_lossy()));
+            }
+        }
+
+        if self.path.exists() {
+            self.watcher.add_path(&qs(self.path.to_string_lossy()));
+        }
+    }
+
+    #[slot(SlotOfQString)]
+    unsafe fn on_changed(self: &Rc<Self>, _path: Ref<QString>) {
+        self.watch();
+
+        // Other files in the same directory changing doesn't matter
+        let last_modified = modified(&self.path);
+
+        if last_modified == self.last_modified.get() {
+            return;
+        }
+
+        self.last_modified.set(last_modified);
+
+        (self.on_change)();
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
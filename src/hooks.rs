@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::notification::Notification;
+
+lazy_static! {
+    static ref HOOKS: RwLock<Vec<Hook>> = RwLock::new(Vec::new());
+    /// What hooks get to see of every notification that hasn't been closed yet
+    static ref NOTIFICATIONS: Mutex<HashMap<u32, HookNotification>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Event {
+    Arrived,
+    Shown,
+    Closed,
+    Action,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Reason {
+    Expired,
+    Dismissed,
+    Closed,
+    Undefined,
+}
+
+impl Reason {
+    /// The reasons from the spec's NotificationClosed signal.
+    fn from_code(code: u32) -> Reason {
+        match code {
+            1 => Reason::Expired,
+            2 => Reason::Dismissed,
+            3 => Reason::Closed,
+            _ => Reason::Undefined,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Reason::Expired => "expired",
+            Reason::Dismissed => "dismissed",
+            Reason::Closed => "closed",
+            Reason::Undefined => "undefined",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum Input {
+    /// Fields are passed as KRUSTYFY_* environment variables
+    #[default]
+    Env,
+    /// Fields are also written to stdin as a JSON object
+    Json,
+}
+
+/// A `[[hook]]` in config.toml.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    event: Event,
+    command: String,
+    /// Only run for notifications closed for this reason
+    reason: Option<Reason>,
+    /// Only run for this app, matched against its name or desktop entry
+    app_name: Option<String>,
+    #[serde(default)]
+    input: Input,
+    /// In milliseconds
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    5000
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+struct HookNotification {
+    id: u32,
+    app_name: String,
+    app_icon: String,
+    summary: String,
+    body: String,
+    actions: Vec<String>,
+    desktop_entry: String,
+    urgency: u8,
+    category: String,
+    sender: String,
+    expire_timeout: i32,
+}
+
+#[derive(Debug, Serialize)]
+struct Payload {
+    event: Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<Reason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<String>,
+    #[serde(flatten)]
+    notification: HookNotification,
+}
+
+impl Payload {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let urgency = match self.notification.urgency {
+            0 => "low",
+            2 => "critical",
+            _ => "normal",
+        };
+
+        let mut env = vec![
+            ("KRUSTYFY_EVENT", event_name(self.event).to_string()),
+            ("KRUSTYFY_ID", self.notification.id.to_string()),
+            ("KRUSTYFY_APP_NAME", self.notification.app_name.clone()),
+            ("KRUSTYFY_APP_ICON", self.notification.app_icon.clone()),
+            ("KRUSTYFY_SUMMARY", self.notification.summary.clone()),
+            ("KRUSTYFY_BODY", self.notification.body.clone()),
+            (
+                "KRUSTYFY_DESKTOP_ENTRY",
+                self.notification.desktop_entry.clone(),
+            ),
+            ("KRUSTYFY_URGENCY", urgency.to_string()),
+            ("KRUSTYFY_CATEGORY", self.notification.category.clone()),
+            ("KRUSTYFY_SENDER", self.notification.sender.clone()),
+            (
+                "KRUSTYFY_EXPIRE_TIMEOUT",
+                self.notification.expire_timeout.to_string(),
+            ),
+        ];
+
+        if let Some(reason) = self.reason {
+            env.push(("KRUSTYFY_REASON", reason.name().to_string()));
+        }
+
+        if let Some(action) = &self.action {
+            env.push(("KRUSTYFY_ACTION", action.clone()));
+        }
+
+        env
+    }
+}
+
+fn event_name(event: Event) -> &'static str {
+    match event {
+        Event::Arrived => "arrived",
+        Event::Shown => "shown",
+        Event::Closed => "closed",
+        Event::Action => "action",
+    }
+}
+
+/// Called for every notification that made it past the rules.
+pub fn arrived(notification: &Notification) {
+    // Nothing needs to be remembered when there are no hooks to give it to
+    if HOOKS
+        .read()
+        .expect("could not acquire hooks lock")
+        .is_empty()
+    {
+        return;
+    }
+
+    let hook_notification = HookNotification {
+        id: notification.notification_id,
+        app_name: notification.app_name.clone(),
+        app_icon: notification.app_icon.clone(),
+        summary: notification.summary.clone(),
+        body: notification.body.clone(),
+        actions: notification.actions.clone(),
+        desktop_entry: notification.desktop_entry.clone(),
+        urgency: notification.urgency,
+        category: notification.category.clone(),
+        sender: notification.sender.clone(),
+        expire_timeout: notification.expire_timeout,
+    };
+
+    NOTIFICATIONS
+        .lock()
+        .expect("could not acquire hook notifications lock")
+        .insert(hook_notification.id, hook_notification.clone());
+
+    run(Payload {
+        event: Event::Arrived,
+        reason: None,
+        action: None,
+        notification: hook_notification,
+    });
+}
+
+/// Called when a notification first shows up on screen.
+pub fn shown(notification_id: u32) {
+    run(Payload {
+        event: Event::Shown,
+        reason: None,
+        action: None,
+        notification: known_notification(notification_id),
+    });
+}
+
+/// Called with the same reason the NotificationClosed signal is sent with.
+pub fn closed(notification_id: u32, reason: u32) {
+    let notification = NOTIFICATIONS
+        .lock()
+        .expect("could not acquire hook notifications lock")
+        .remove(&notification_id)
+        .unwrap_or(HookNotification {
+            id: notification_id,
+            ..Default::default()
+        });
+
+    run(Payload {
+        event: Event::Closed,
+        reason: Some(Reason::from_code(reason)),
+        action: None,
+        notification,
+    });
+}
+
+pub fn action(notification_id: u32, action: &str) {
+    run(Payload {
+        event: Event::Action,
+        reason: None,
+        action: Some(action.to_string()),
+        notification: known_notification(notification_id),
+    });
+}
+
+fn known_notification(notification_id: u32) -> HookNotification {
+    NOTIFICATIONS
+        .lock()
+        .expect("could not acquire hook notifications lock")
+        .get(&notification_id)
+        .cloned()
+        .unwrap_or(HookNotification {
+            id: notification_id,
+            ..Default::default()
+        })
+}
+
+/// The hooks run on the tokio runtime, so this never waits for them.
+fn run(payload: Payload) {
+    let hooks = HOOKS.read().expect("could not acquire hooks lock");
+
+    for hook in hooks.iter() {
+        if hook.event != payload.event {
+            continue;
+        }
+
+        if hook.reason.is_some() && hook.reason != payload.reason {
+            continue;
+        }
+
+        if let Some(app_name) = &hook.app_name {
+            if !app_name.eq_ignore_ascii_case(&payload.notification.app_name)
+                && !app_name.eq_ignore_ascii_case(&payload.notification.desktop_entry)
+            {
+                continue;
+            }
+        }
+
+        let json = match hook.input {
+            Input::Env => None,
+            Input::Json => match serde_json::to_vec(&payload) {
+                Ok(json) => Some(json),
+                Err(err) => {
+                    eprintln!("Could not serialize notification for hook: {}", err);
+                    continue;
+                }
+            },
+        };
+
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&hook.command)
+            .envs(payload.env())
+            .stdin(if json.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .kill_on_drop(true);
+
+        let command_line = hook.command.clone();
+        let timeout = Duration::from_millis(hook.timeout);
+
+        tokio::spawn(async move {
+            let mut child = match command.spawn() {
+                Ok(child) => child,
+                Err(err) => {
+                    eprintln!("Could not run hook `{}`: {}", command_line, err);
+                    return;
+                }
+            };
+
+            if let (Some(json), Some(mut stdin)) = (json, child.stdin.take()) {
+                // A hook that doesn't read its input isn't an error
+                let _ = stdin.write_all(&json).await;
+            }
+
+            match tokio::time::timeout(timeout, child.wait()).await {
+                Ok(Ok(status)) if !status.success() => {
+                    eprintln!("Hook `{}` exited with {}", command_line, status);
+                }
+                Ok(Err(err)) => eprintln!("Hook `{}` failed: {}", command_line, err),
+                Err(_) => {
+                    eprintln!("Hook `{}` timed out and was killed", command_line);
+                    let _ = child.kill().await;
+                }
+                _ => (),
+            }
+        });
+    }
+}
+
+/// Replaces the hooks, every time the config is loaded.
+pub fn set_hooks(hooks: Vec<Hook>) {
+    *HOOKS.write().expect("could not acquire hooks lock") = hooks;
+}
@@ -1,14 +1,57 @@
 use cpp_core::{CppBox, Ref};
 
-use qt_core::{qs, QFileInfo, QString};
-use qt_gui::{QIcon, QImage, QPixmap};
+use qt_core::{qs, AspectRatioMode, GlobalColor, QFileInfo, QRectF, QString, TransformationMode};
+use qt_gui::{q_painter::RenderHint, QColor, QIcon, QImage, QPainter, QPainterPath, QPixmap};
 use qt_widgets::QFileIconProvider;
 
 use crate::notification::ImageData;
 
 const DEFAULT_ICON: &str = "notifications";
 
-pub unsafe fn find_icon(desktop_entry: &String) -> CppBox<QPixmap> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    // Whatever its aspect ratio
+    Stretch,
+    // All of it, with empty space on the sides
+    Contain,
+    // All of the label, with the sides that don't fit cut off
+    Cover,
+    // As big as it is, centered
+    Original,
+}
+
+impl Fit {
+    pub fn from_name(name: &str) -> Option<Fit> {
+        match name {
+            "stretch" => Some(Fit::Stretch),
+            "contain" => Some(Fit::Contain),
+            "cover" => Some(Fit::Cover),
+            "original" => Some(Fit::Original),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clip {
+    Rounded,
+    Circle,
+    None,
+}
+
+impl Clip {
+    pub fn from_name(name: &str) -> Option<Clip> {
+        match name {
+            "rounded" => Some(Clip::Rounded),
+            "circle" => Some(Clip::Circle),
+            "none" => Some(Clip::None),
+            _ => None,
+        }
+    }
+}
+
+// The notification icon if the app doesn't have one
+pub unsafe fn find_icon(desktop_entry: &String) -> CppBox<QIcon> {
     let desktop_entry_lowercase = desktop_entry.as_str().to_lowercase();
 
     let qstr = QString::from_std_str(desktop_entry_lowercase.as_str());
@@ -27,17 +70,13 @@ pub unsafe fn find_icon(desktop_entry: &String) -> CppBox<QPixmap> {
         let icon_provider = QFileIconProvider::new();
 
         if info.exists_0a() {
-            let icon = icon_provider.icon_q_file_info(info.as_ref());
-
-            let pixmap = icon.pixmap_int(64);
-
-            return pixmap;
+            return icon_provider.icon_q_file_info(info.as_ref());
         }
 
-        return QIcon::from_theme_1a(QString::from_std_str(DEFAULT_ICON).as_ref()).pixmap_int(64);
+        return QIcon::from_theme_1a(QString::from_std_str(DEFAULT_ICON).as_ref());
     }
 
-    QIcon::from_theme_1a(icon_name).pixmap_int(64)
+    QIcon::from_theme_1a(icon_name)
 }
 
 pub unsafe fn parse_image(image_data: ImageData) -> CppBox<QPixmap> {
@@ -73,3 +112,90 @@ pub unsafe fn load_image(image_path: String) -> CppBox<QPixmap> {
 
     pixmap
 }
+
+// `width` and `height` are logical pixels, and `roundness` goes from 0 to 99
+pub unsafe fn render(
+    pixmap: &QPixmap,
+    width: i32,
+    height: i32,
+    fit: Fit,
+    clip: Clip,
+    roundness: i32,
+    device_pixel_ratio: f64,
+) -> CppBox<QPixmap> {
+    let target = QPixmap::from_2_int(
+        (width as f64 * device_pixel_ratio).round() as i32,
+        (height as f64 * device_pixel_ratio).round() as i32,
+    );
+    target.set_device_pixel_ratio(device_pixel_ratio);
+    target.fill_1a(&QColor::from_global_color(GlobalColor::Transparent));
+
+    if pixmap.is_null() || width <= 0 || height <= 0 {
+        return target;
+    }
+
+    let (width, height) = (width as f64, height as f64);
+
+    // Its size in logical pixels, like the target's
+    let source_width = pixmap.width() as f64 / pixmap.device_pixel_ratio_f();
+    let source_height = pixmap.height() as f64 / pixmap.device_pixel_ratio_f();
+
+    let (drawn_width, drawn_height) = match fit {
+        Fit::Stretch => (width, height),
+        Fit::Contain => {
+            let scale = (width / source_width).min(height / source_height);
+            (source_width * scale, source_height * scale)
+        }
+        Fit::Cover => {
+            let scale = (width / source_width).max(height / source_height);
+            (source_width * scale, source_height * scale)
+        }
+        Fit::Original => (source_width, source_height),
+    };
+
+    let painter = QPainter::new_1a(&target);
+
+    painter.set_render_hints_2a(
+        RenderHint::HighQualityAntialiasing
+            | RenderHint::SmoothPixmapTransform
+            | RenderHint::Antialiasing,
+        true,
+    );
+
+    let path = QPainterPath::new_0a();
+
+    match clip {
+        Clip::Rounded => {
+            path.add_round_rect_6a(0.0, 0.0, width, height, roundness, roundness);
+            painter.set_clip_path_1a(&path);
+        }
+        Clip::Circle => {
+            path.add_ellipse_4a(0.0, 0.0, width, height);
+            painter.set_clip_path_1a(&path);
+        }
+        Clip::None => (),
+    }
+
+    // Scaled in device pixels, so it isn't scaled again (and blurred) when it's drawn
+    let scaled = pixmap.scaled_2_int_aspect_ratio_mode_transformation_mode(
+        (drawn_width * device_pixel_ratio).round() as i32,
+        (drawn_height * device_pixel_ratio).round() as i32,
+        AspectRatioMode::IgnoreAspectRatio,
+        TransformationMode::SmoothTransformation,
+    );
+
+    painter.draw_pixmap_q_rect_f_q_pixmap_q_rect_f(
+        &QRectF::from_4_double(
+            (width - drawn_width) / 2.0,
+            (height - drawn_height) / 2.0,
+            drawn_width,
+            drawn_height,
+        ),
+        &scaled,
+        &QRectF::from_4_double(0.0, 0.0, scaled.width() as f64, scaled.height() as f64),
+    );
+
+    painter.end();
+
+    target
+}
@@ -1,42 +1,113 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::env;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use cpp_core::NullPtr;
 use errors::KrustifyError;
 use qt_core::{
-    qs, ConnectionType, QCoreApplication, QString, SignalOfInt, SignalOfQString, WidgetAttribute,
-    WindowType,
+    qs, ApplicationAttribute, ConnectionType, QCoreApplication, QString, SignalOfInt,
+    SignalOfQString, SlotOfInt, SlotOfQString, WidgetAttribute, WindowType,
 };
 use qt_widgets::{QApplication, QFrame, QMainWindow};
 use tokio::{
     self,
-    sync::mpsc::{self, Sender},
+    sync::mpsc::{self, Sender, UnboundedSender},
 };
 use uuid::Uuid;
-use zbus::{dbus_interface, zvariant::Array, ConnectionBuilder};
+use zbus::{dbus_interface, zvariant::Array, ConnectionBuilder, MessageHeader};
 use zvariant::Value;
 
 use notification::{ImageData, Notification};
 use notification_spawner::NotificationSpawner;
 
 use crate::dbus_signal::{DbusMethod, DbusSignal};
-use crate::settings::{load_settings, SETTINGS};
+use crate::rate_limiter::{RateLimiter, Verdict};
+use crate::settings::{find_screen, load_settings, SETTINGS};
 use crate::tray_menu::generate_tray;
 
+mod color_scheme;
+mod config;
 mod dbus_signal;
 mod errors;
+mod file_watcher;
+mod hooks;
 mod image_handler;
 mod notification;
 mod notification_spawner;
 mod notification_widget;
+mod overflow_indicator;
+mod rate_limiter;
+mod rules;
+mod scripting;
 mod settings;
+mod template;
+mod theme_assets;
+mod theme_manager;
+mod themes;
 mod tray_menu;
 
 //static
 struct NotificationHandler {
     count: u32,
     dbus_method_sender: Sender<DbusMethod>,
+    dbus_signal_sender: UnboundedSender<DbusSignal>,
+    rate_limiter: RateLimiter,
+    /// Who sent each notification that hasn't been closed yet
+    live_notifications: Arc<Mutex<HashMap<u32, String>>>,
+}
+
+impl NotificationHandler {
+    fn warn_flooding(&mut self, app_name: &str) {
+        self.count += 1;
+
+        let warning = Notification {
+            app_name: String::from(env!("CARGO_PKG_NAME")),
+            summary: format!("{app_name} is flooding notifications"),
+            body: String::from("Some of its notifications won't be shown until it calms down."),
+            expire_timeout: -1,
+            notification_id: self.count,
+            urgency: 1,
+            ..Default::default()
+        };
+
+        let dbus_method_sender = self.dbus_method_sender.clone();
+
+        // The flood is likely filling up the channel, so it's not waited on here
+        tokio::spawn(async move {
+            if let Err(err) = dbus_method_sender
+                .send(DbusMethod::Notify {
+                    notification: warning,
+                })
+                .await
+            {
+                eprintln!("Could not show the flooding warning: {err}");
+            }
+        });
+    }
+
+    /// Lets the client know a notification it sent is gone, even though it never showed up.
+    fn close_unshown(&self, notification_id: u32, reason: u32) {
+        let dbus_signal_sender = self.dbus_signal_sender.clone();
+
+        tokio::spawn(async move {
+            // Give the client a chance to get the ID back before telling it it's gone
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            dbus_signal_sender
+                .send(DbusSignal::NotificationClosed {
+                    notification_id,
+                    reason,
+                })
+                .expect("failed to send signal");
+        });
+    }
 }
 
 #[dbus_interface(name = "org.freedesktop.Notifications")]
@@ -62,6 +133,7 @@ impl NotificationHandler {
         actions: Vec<String>,
         hints: HashMap<String, Value<'_>>,
         expire_timeout: i32,
+        #[zbus(header)] header: MessageHeader<'_>,
     ) -> zbus::fdo::Result<u32> {
         let desktop_entry = if hints.contains_key("desktop-entry") {
             zbus::zvariant::Str::try_from(&hints["desktop-entry"])
@@ -142,6 +214,32 @@ impl NotificationHandler {
             None
         };
 
+        let urgency = if hints.contains_key("urgency") {
+            u8::try_from(&hints["urgency"]).map_err(KrustifyError::from)?
+        } else {
+            1
+        };
+
+        let category = if hints.contains_key("category") {
+            zbus::zvariant::Str::try_from(&hints["category"])
+                .map_err(KrustifyError::from)?
+                .to_string()
+        } else {
+            String::new()
+        };
+
+        // Not in the spec, so it's not an error if it isn't a string
+        let synchronous = hints.get("x-canonical-private-synchronous").map(|value| {
+            zbus::zvariant::Str::try_from(value)
+                .map(|value| value.to_string())
+                .unwrap_or_default()
+        });
+
+        let sender = match header.sender() {
+            Ok(Some(sender)) => sender.to_string(),
+            _ => String::new(),
+        };
+
         let notification_id = if replaces_id == 0 {
             self.count += 1;
             self.count
@@ -149,7 +247,7 @@ impl NotificationHandler {
             replaces_id
         };
 
-        let notification = Notification {
+        let mut notification = Notification {
             app_name,
             replaces_id,
             app_icon,
@@ -161,8 +259,64 @@ impl NotificationHandler {
             expire_timeout,
             notification_id,
             desktop_entry,
+            urgency,
+            category,
+            sender,
+            synchronous,
+            timeout: None,
+            theme: None,
+            screen: None,
+            silent: false,
         };
 
+        // The user asked for it to go away, so it counts as dismissed
+        if !rules::apply(&mut notification) || !scripting::apply(&mut notification, &hints) {
+            self.close_unshown(notification_id, 2);
+
+            return Ok(notification_id);
+        }
+
+        hooks::arrived(&notification);
+
+        // Replacing one of its own notifications doesn't add a new one, so updates like
+        // progress bars are never held back
+        let replaces_live = replaces_id != 0
+            && self
+                .live_notifications
+                .lock()
+                .expect("could not acquire live notifications lock")
+                .get(&replaces_id)
+                == Some(&notification.sender);
+
+        if !replaces_live {
+            let rate_limit =
+                settings::rate_limit_for(&notification.app_name, &notification.desktop_entry);
+
+            if let Some(rate_limit) = rate_limit {
+                let app = if notification.desktop_entry.is_empty() {
+                    notification.app_name.to_lowercase()
+                } else {
+                    notification.desktop_entry.to_lowercase()
+                };
+
+                if let Verdict::Drop { warn } = self.rate_limiter.check(&app, rate_limit) {
+                    if warn {
+                        self.warn_flooding(&notification.app_name);
+                    }
+
+                    // It was never shown, so it neither expired nor got dismissed
+                    self.close_unshown(notification_id, 4);
+
+                    return Ok(notification_id);
+                }
+            }
+        }
+
+        self.live_notifications
+            .lock()
+            .expect("could not acquire live notifications lock")
+            .insert(notification_id, notification.sender.clone());
+
         self.dbus_method_sender
             .send(DbusMethod::Notify { notification })
             .await
@@ -203,14 +357,112 @@ impl NotificationHandler {
     }
 }
 
+/// Creates the (invisible) window notifications are stacked in, on the given screen.
+unsafe fn create_stack(
+    screen_id: i32,
+    dbus_signal_sender: UnboundedSender<DbusSignal>,
+) -> Rc<NotificationSpawner> {
+    let main_window = QMainWindow::new_0a();
+
+    let desktop = QApplication::desktop();
+
+    let topleft = desktop.screen_geometry_int(screen_id).top_left();
+
+    main_window.set_window_flags(
+        WindowType::WindowTransparentForInput
+            | WindowType::WindowStaysOnTopHint
+            | WindowType::FramelessWindowHint
+            | WindowType::BypassWindowManagerHint
+            | WindowType::X11BypassWindowManagerHint,
+    );
+
+    main_window.set_attribute_1a(WidgetAttribute::WATranslucentBackground);
+    main_window.set_attribute_1a(WidgetAttribute::WADeleteOnClose);
+    main_window.set_attribute_1a(WidgetAttribute::WANoSystemBackground);
+    main_window.set_style_sheet(&qs("background-color: transparent;"));
+
+    let main_frame = QFrame::new_1a(main_window.as_ptr());
+
+    main_frame.set_attribute_1a(WidgetAttribute::WATranslucentBackground);
+    main_frame.set_style_sheet(&qs("background-color: transparent;"));
+
+    main_window.set_geometry_4a(topleft.x(), 0, 0, 0);
+
+    main_window.show();
+
+    // It's deleted when it's closed
+    main_window.into_ptr();
+
+    let spawner = NotificationSpawner::new(dbus_signal_sender, main_frame, screen_id);
+
+    spawner.init();
+
+    spawner
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    // Only has an effect before QApplication exists. Without it, scaled screens report a
+    // device pixel ratio of 1 on Qt 5 and icons come out blurry
+    unsafe {
+        QCoreApplication::set_attribute_1a(ApplicationAttribute::AAEnableHighDpiScaling);
+        QCoreApplication::set_attribute_1a(ApplicationAttribute::AAUseHighDpiPixmaps);
+    }
+
+    // Theme commands run and exit, without taking over the notification service
+    match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("--check-theme"), Some(theme_dir)) => {
+            let theme_dir = PathBuf::from(theme_dir);
+
+            QApplication::init(move |_app| unsafe { themes::print_report(&theme_dir) });
+        }
+        (Some("--install-theme"), Some(archive)) => {
+            match theme_manager::install(Path::new(archive)) {
+                Ok(name) => println!("Installed the \"{name}\" theme"),
+                Err(err) => {
+                    eprintln!("Could not install {archive}: {err}");
+                    process::exit(1);
+                }
+            }
+
+            process::exit(0);
+        }
+        (Some("--remove-theme"), Some(name)) => {
+            if let Err(err) = theme_manager::remove(name) {
+                eprintln!("Could not remove the \"{name}\" theme: {err}");
+                process::exit(1);
+            }
+
+            process::exit(0);
+        }
+        (Some("--list-themes"), None) => {
+            theme_manager::print_list();
+            process::exit(0);
+        }
+        // A theme command missing its argument
+        (Some("--check-theme" | "--install-theme" | "--remove-theme" | "--list-themes"), _) => {
+            eprintln!(
+                "Usage: {0} [--check-theme <theme directory> | --install-theme <.tar.gz or .zip> | --remove-theme <name> | --list-themes]",
+                args[0]
+            );
+            process::exit(2);
+        }
+        // Qt's own options, like -platform, are left for QApplication
+        _ => (),
+    }
+
     let (dbus_method_sender, mut dbus_method_receiver) = mpsc::channel(5);
     let (dbus_signal_sender, mut dbus_signal_receiver) = mpsc::unbounded_channel();
+    let live_notifications = Arc::new(Mutex::new(HashMap::new()));
 
     let notification_handler = NotificationHandler {
         count: 0,
         dbus_method_sender,
+        dbus_signal_sender: dbus_signal_sender.clone(),
+        rate_limiter: RateLimiter::default(),
+        live_notifications: live_notifications.clone(),
     };
     let connection = ConnectionBuilder::session()?
         .name("org.freedesktop.Notifications")?
@@ -218,35 +470,48 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .build()
         .await?;
 
-    tokio::spawn(async move {
-        while let Some(signal) = dbus_signal_receiver.recv().await {
-            match signal {
-                DbusSignal::ActionInvoked { notification_id } => {
-                    connection
-                        .emit_signal(
-                            None::<()>,
-                            "/org/freedesktop/Notifications",
-                            "org.freedesktop.Notifications",
-                            "ActionInvoked",
-                            &(notification_id as u32, "default"),
-                        )
-                        .await
-                        .expect("could not emit ActionInvoked signal");
-                }
-                DbusSignal::NotificationClosed {
-                    notification_id,
-                    reason,
-                } => {
-                    connection
-                        .emit_signal(
-                            None::<()>,
-                            "/org/freedesktop/Notifications",
-                            "org.freedesktop.Notifications",
-                            "NotificationClosed",
-                            &(notification_id, reason),
-                        )
-                        .await
-                        .expect("could not emit NotificationClosed signal");
+    tokio::spawn({
+        let live_notifications = live_notifications.clone();
+
+        async move {
+            while let Some(signal) = dbus_signal_receiver.recv().await {
+                match signal {
+                    DbusSignal::ActionInvoked { notification_id } => {
+                        hooks::action(notification_id as u32, "default");
+
+                        connection
+                            .emit_signal(
+                                None::<()>,
+                                "/org/freedesktop/Notifications",
+                                "org.freedesktop.Notifications",
+                                "ActionInvoked",
+                                &(notification_id as u32, "default"),
+                            )
+                            .await
+                            .expect("could not emit ActionInvoked signal");
+                    }
+                    DbusSignal::NotificationClosed {
+                        notification_id,
+                        reason,
+                    } => {
+                        live_notifications
+                            .lock()
+                            .expect("could not acquire live notifications lock")
+                            .remove(&notification_id);
+
+                        hooks::closed(notification_id, reason);
+
+                        connection
+                            .emit_signal(
+                                None::<()>,
+                                "/org/freedesktop/Notifications",
+                                "org.freedesktop.Notifications",
+                                "NotificationClosed",
+                                &(notification_id, reason),
+                            )
+                            .await
+                            .expect("could not emit NotificationClosed signal");
+                    }
                 }
             }
         }
@@ -258,51 +523,125 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         load_settings();
 
-        let main_window = QMainWindow::new_0a();
+        let _script_watcher = scripting::watch_script();
 
-        let desktop = QApplication::desktop();
+        // One stack per screen, created the first time something is shown there
+        let spawners: Rc<RefCell<HashMap<i32, Rc<NotificationSpawner>>>> =
+            Rc::new(RefCell::new(HashMap::new()));
 
-        let topleft = desktop
-            .screen_geometry_int(SETTINGS.screen.id.clone())
-            .top_left();
-
-        main_window.set_window_flags(
-            WindowType::WindowTransparentForInput
-                | WindowType::WindowStaysOnTopHint
-                | WindowType::FramelessWindowHint
-                | WindowType::BypassWindowManagerHint
-                | WindowType::X11BypassWindowManagerHint,
+        spawners.borrow_mut().insert(
+            SETTINGS.screen.id,
+            create_stack(SETTINGS.screen.id, dbus_signal_sender.clone()),
         );
 
-        main_window.set_attribute_1a(WidgetAttribute::WATranslucentBackground);
-        main_window.set_attribute_1a(WidgetAttribute::WADeleteOnClose);
-        main_window.set_attribute_1a(WidgetAttribute::WANoSystemBackground);
-        main_window.set_style_sheet(&qs("background-color: transparent;"));
+        let spawn_slot = SlotOfQString::new(NullPtr, {
+            let spawners = spawners.clone();
+
+            move |guid| {
+                let screen_id = match notification_spawner::NOTIFICATION_LIST
+                    .lock()
+                    .expect("could not acquire lock to notification list")
+                    .get(&guid.to_std_string())
+                {
+                    Some(notification) => notification
+                        .screen
+                        .as_deref()
+                        .and_then(|screen| find_screen(screen))
+                        .unwrap_or(SETTINGS.screen.id),
+                    None => return,
+                };
+
+                // Stacks left empty on other screens are closed rather than kept around
+                spawners.borrow_mut().retain(|&id, spawner| {
+                    if id == screen_id || id == SETTINGS.screen.id || !spawner.is_idle() {
+                        return true;
+                    }
 
-        let main_frame = QFrame::new_1a(main_window.as_ptr());
+                    spawner.close();
+                    false
+                });
 
-        main_frame.set_attribute_1a(WidgetAttribute::WATranslucentBackground);
-        main_frame.set_style_sheet(&qs("background-color: transparent;"));
+                let spawner = spawners
+                    .borrow_mut()
+                    .entry(screen_id)
+                    .or_insert_with(|| create_stack(screen_id, dbus_signal_sender.clone()))
+                    .clone();
 
-        main_window.set_geometry_4a(topleft.x(), 0, 0, 0);
+                spawner.on_spawn_notification(guid);
+            }
+        });
 
-        main_window.show();
+        // Notifications on screen take on the changes as a theme is being worked on
+        let _theme_watcher = themes::ThemeWatcher::new({
+            let spawners = spawners.clone();
 
-        let spawner = NotificationSpawner::new(dbus_signal_sender, main_frame);
+            move |theme| {
+                themes::forget_checked(theme);
 
-        spawner.init();
+                // A broken template is reported, and whatever is on screen is left alone
+                if !themes::check_theme(theme) {
+                    return;
+                }
+
+                template::forget_template(theme);
+
+                for spawner in spawners.borrow().values() {
+                    spawner.reload_theme(theme);
+                }
+            }
+        });
+
+        // Notifications on screen only pick up reduced_motion when they're built again
+        let reduced_motion = Rc::new(Cell::new(SETTINGS.reduced_motion.value));
+
+        let _config_watcher = config::watch_config({
+            let spawners = spawners.clone();
+            let reduced_motion = reduced_motion.clone();
+
+            move || {
+                if reduced_motion.replace(SETTINGS.reduced_motion.value)
+                    != SETTINGS.reduced_motion.value
+                {
+                    for spawner in spawners.borrow().values() {
+                        spawner.restyle();
+                    }
+                }
+            }
+        });
+
+        // Light and dark variants are swapped as the desktop switches between them, and
+        // animations follow it when reduced_motion is auto
+        let _scheme_watcher = color_scheme::SchemeWatcher::new({
+            let spawners = spawners.clone();
+
+            move || {
+                SETTINGS.reduced_motion.refresh();
+                reduced_motion.set(SETTINGS.reduced_motion.value);
+
+                for spawner in spawners.borrow().values() {
+                    spawner.restyle();
+                }
+            }
+        });
+
+        tokio::spawn(color_scheme::watch_desktop(_scheme_watcher.notifier()));
+
+        let external_close_slot = SlotOfInt::new(NullPtr, {
+            let spawners = spawners.clone();
+
+            move |notification_id| {
+                for spawner in spawners.borrow().values() {
+                    spawner.on_external_close(notification_id);
+                }
+            }
+        });
 
         let notitification_signal = SignalOfQString::new();
-        notitification_signal.connect_with_type(
-            ConnectionType::QueuedConnection,
-            &spawner.slot_on_spawn_notification(),
-        );
+        notitification_signal.connect_with_type(ConnectionType::QueuedConnection, &spawn_slot);
 
         let closed_notification_signal = SignalOfInt::new();
-        closed_notification_signal.connect_with_type(
-            ConnectionType::QueuedConnection,
-            &spawner.slot_on_external_close(),
-        );
+        closed_notification_signal
+            .connect_with_type(ConnectionType::QueuedConnection, &external_close_slot);
 
         let ref_notification_signal = notitification_signal
             .as_raw_ref()
@@ -321,13 +660,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         });
                     }
                     DbusMethod::Notify { notification } => {
-                        if !SETTINGS.do_not_disturb.value {
+                        if !settings::do_not_disturb() {
                             let guid = Uuid::new_v4().to_string();
                             let mut list = notification_spawner::NOTIFICATION_LIST
                                 .lock()
                                 .expect("could not acquire lock to notification list");
                             list.insert(guid.clone(), notification);
                             ref_notification_signal.emit(&QString::from_std_str(&guid));
+                        } else {
+                            // It's never shown, so it's never closed either
+                            live_notifications
+                                .lock()
+                                .expect("could not acquire live notifications lock")
+                                .remove(&notification.notification_id);
                         }
                     }
                 }
@@ -31,7 +31,7 @@ impl ImageData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Notification {
     pub app_name: String,
     pub replaces_id: u32,
@@ -44,4 +44,17 @@ pub struct Notification {
     pub expire_timeout: i32,
     pub notification_id: u32,
     pub desktop_entry: String,
+    /// 0 (low), 1 (normal) or 2 (critical)
+    pub urgency: u8,
+    pub category: String,
+    /// Unique bus name of whoever sent it
+    pub sender: String,
+    /// The x-canonical-private-synchronous hint, set by things like volume popups
+    pub synchronous: Option<String>,
+    // Set by rules
+    /// Used instead of the theme's duration, unlike the expire_timeout the app asked for
+    pub timeout: Option<i32>,
+    pub theme: Option<String>,
+    pub screen: Option<String>,
+    pub silent: bool,
 }
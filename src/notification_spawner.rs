@@ -1,28 +1,31 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
 use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-use cpp_core::{Ptr, Ref, StaticUpcast};
+use cpp_core::{CppBox, Ptr, Ref, StaticUpcast};
+use device_query::{DeviceQuery, DeviceState, Keycode};
 
 use linked_hash_map::LinkedHashMap;
 
-use qt_widgets::QFrame;
+use qt_widgets::{QApplication, QFrame};
 use tokio::sync::mpsc::UnboundedSender;
 
 use lazy_static::lazy_static;
 
 use qt_core::{
-    qs, slot, ConnectionType, QBox, QObject, QString, QTimer, SignalNoArgs, SignalOfInt,
-    SignalOfQString, SlotNoArgs, SlotOfInt, SlotOfQString,
+    qs, slot, ConnectionType, QBox, QDateTime, QObject, QRect, QString, QTimer, SignalNoArgs,
+    SignalOfBool, SignalOfInt, SignalOfQString, SlotNoArgs, SlotOfInt, SlotOfQString,
 };
 use uuid::Uuid;
 
 use crate::errors::KrustifyError;
+use crate::overflow_indicator::OverflowIndicator;
+use crate::settings::{Quirk, SETTINGS};
 use crate::{
-    dbus_signal::DbusSignal,
-    image_handler,
-    notification::{ImageData, Notification},
-    notification_widget::notifications::NotificationWidget,
+    dbus_signal::DbusSignal, hooks, image_handler, notification::Notification,
+    notification_widget::notifications::NotificationWidget, template, theme_assets, themes,
 };
 
 lazy_static! {
@@ -30,9 +33,22 @@ lazy_static! {
         Mutex::new(HashMap::new());
 }
 
+fn group_key(app_name: &str, desktop_entry: &str) -> String {
+    if !desktop_entry.is_empty() {
+        desktop_entry.to_lowercase()
+    } else {
+        app_name.to_lowercase()
+    }
+}
+
 pub struct NotificationSpawner {
     widget_list: Mutex<LinkedHashMap<String, Rc<NotificationWidget>>>,
-    check_hover: QBox<SignalNoArgs>,
+    pending_list: Mutex<VecDeque<Notification>>,
+    overflow_indicator: RefCell<Option<OverflowIndicator>>,
+    expanded: Cell<bool>,
+    // Read once per tick for the whole stack
+    device_state: DeviceState,
+    check_hover: QBox<SignalOfBool>,
     signal_sender: UnboundedSender<DbusSignal>,
     timer: QBox<QTimer>,
     reorder_signal: QBox<SignalNoArgs>,
@@ -40,6 +56,9 @@ pub struct NotificationSpawner {
     close_signal: QBox<SignalOfQString>,
     qobject: QBox<QObject>,
     main_window: QBox<QFrame>,
+    screen_id: i32,
+    // The scale the notifications were last rendered for
+    device_pixel_ratio: Cell<f64>,
 }
 
 impl StaticUpcast<QObject> for NotificationSpawner {
@@ -52,16 +71,17 @@ impl NotificationSpawner {
     pub fn new(
         signal_sender: UnboundedSender<DbusSignal>,
         main_window: QBox<QFrame>,
+        screen_id: i32,
     ) -> Rc<NotificationSpawner> {
         unsafe {
             let widget_list = Mutex::new(LinkedHashMap::new());
 
+            let pending_list = Mutex::new(VecDeque::new());
+
             let timer = QTimer::new_0a();
             timer.set_interval(100);
 
-            let check_hover = SignalNoArgs::new();
-
-            timer.timeout().connect(&check_hover);
+            let check_hover = SignalOfBool::new();
 
             let reorder_signal = SignalNoArgs::new();
 
@@ -71,8 +91,14 @@ impl NotificationSpawner {
 
             let qobject = QObject::new_0a();
 
+            let device_pixel_ratio = Cell::new(main_window.device_pixel_ratio_f());
+
             Rc::new(Self {
                 widget_list,
+                pending_list,
+                overflow_indicator: RefCell::new(None),
+                expanded: Cell::new(false),
+                device_state: DeviceState::new(),
                 check_hover,
                 signal_sender,
                 timer,
@@ -81,6 +107,8 @@ impl NotificationSpawner {
                 close_signal,
                 qobject,
                 main_window,
+                screen_id,
+                device_pixel_ratio,
             })
         }
     }
@@ -88,6 +116,9 @@ impl NotificationSpawner {
     pub unsafe fn init(self: &Rc<Self>) {
         self.timer.start_0a();
 
+        self.timer.timeout().connect(&self.slot_on_tick());
+        self.timer.timeout().connect(&self.slot_on_check_scale());
+
         self.reorder_signal
             .connect_with_type(ConnectionType::QueuedConnection, &self.slot_on_reorder());
 
@@ -106,20 +137,8 @@ impl NotificationSpawner {
         let notification_option = list.remove(&guid.to_std_string());
 
         if let Some(notification) = notification_option {
-            self.spawn_notification(
-                notification.app_name,
-                notification.replaces_id,
-                notification.app_icon,
-                notification.summary,
-                notification.body,
-                notification.actions,
-                notification.image_data,
-                notification.image_path,
-                notification.expire_timeout,
-                notification.notification_id,
-                notification.desktop_entry,
-            )
-            .expect("failed to spawn notification");
+            self.spawn_notification(notification)
+                .expect("failed to spawn notification");
         } else {
             return;
         }
@@ -128,20 +147,42 @@ impl NotificationSpawner {
     pub unsafe fn get_already_existing_notification<'a>(
         self: &Rc<Self>,
         list: &'a MutexGuard<LinkedHashMap<String, Rc<NotificationWidget>>>,
-        app_name: &String,
-        replaces_id: u32,
+        notification: &Notification,
     ) -> Option<&'a Rc<NotificationWidget>> {
-        for widget in list.values() {
-            let _replaces_id = widget.notification_id.borrow().to_owned();
+        let replaces_id = notification.replaces_id;
 
-            if _replaces_id == replaces_id {
-                return Some(widget);
+        if replaces_id != 0 {
+            let widget = list.values().find(|widget| widget.has_id(replaces_id));
+
+            if widget.is_some() {
+                return widget;
             }
+        }
+
+        let group_key = group_key(&notification.app_name, &notification.desktop_entry);
+
+        let quirks = SETTINGS
+            .quirks
+            .for_app(&notification.app_name, &notification.desktop_entry);
+
+        for quirk in quirks {
+            let mut app_widgets = list
+                .values()
+                .filter(|widget| *widget.group_key.borrow() == group_key);
+
+            let widget = match quirk {
+                Quirk::OffByOneId => Quirk::intended_id(replaces_id)
+                    .and_then(|id| app_widgets.find(|widget| widget.has_id(id))),
+                Quirk::MatchingSummary => {
+                    app_widgets.find(|widget| *widget.summary.borrow() == notification.summary)
+                }
+                Quirk::ReplacePrevious => app_widgets.last(),
+            };
+
+            if let Some(widget) = widget {
+                // The widget takes the new ID so the one the client got back is still valid
+                widget.take_id(notification.notification_id);
 
-            if app_name.eq("discord") && _replaces_id == replaces_id - 1
-            // Fuck you Discord
-            {
-                widget.notification_id.replace(replaces_id);
                 return Some(widget);
             }
         }
@@ -151,90 +192,288 @@ impl NotificationSpawner {
 
     pub unsafe fn spawn_notification(
         self: &Rc<Self>,
-        app_name: String,
-        replaces_id: u32,
-        _app_icon: String,
-        summary: String,
-        body: String,
-        _actions: Vec<String>,
-        image_data: Option<ImageData>,
-        image_path: Option<String>,
-        _expire_timeout: i32,
-        notification_id: u32,
-        desktop_entry: String,
+        notification: Notification,
     ) -> Result<(), KrustifyError> {
         let mut list = self.widget_list.lock()?;
 
         let already_existing_notification =
-            self.get_already_existing_notification(&list, &app_name, replaces_id);
+            self.get_already_existing_notification(&list, &notification);
 
         if let Some(notification_widget) = already_existing_notification {
+            notification_widget.set_expire_timeout(
+                SETTINGS
+                    .durations
+                    .expire_timeout(notification.timeout, notification.urgency),
+            );
             notification_widget.reset_timer();
 
-            self.set_notification_contents(
-                app_name,
-                image_data,
-                image_path,
-                desktop_entry,
-                summary,
-                body,
-                notification_widget,
-            );
-        } else {
-            let guid = Uuid::new_v4().to_string();
+            self.set_notification_contents(notification, notification_widget);
 
-            let _notification_widget = NotificationWidget::new(
-                &self.main_window,
-                &self.close_signal,
-                &self.action_signal,
-                notification_id,
-                guid.clone(),
-            )?;
-
-            self.set_notification_contents(
-                app_name,
-                image_data,
-                image_path,
-                desktop_entry,
-                summary,
-                body,
-                &_notification_widget,
-            );
+            return Ok(());
+        }
 
-            self.check_hover
-                .connect(&_notification_widget.slot_check_hover());
+        if let Some(notification_widget) = self.get_duplicate_notification(&list, &notification) {
+            // The widget takes the newest ID so the one the client got back is still valid
+            notification_widget.take_id(notification.notification_id);
+            notification_widget.last_update.replace(Instant::now());
+            notification_widget.set_arrived(QDateTime::current_date_time());
+
+            let repeat_count = notification_widget.repeat_count.borrow().to_owned() + 1;
+            notification_widget.set_repeat_count(repeat_count);
+            notification_widget.reset_timer();
+
+            return Ok(());
+        }
+
+        let mut pending_list = self.pending_list.lock()?;
+
+        // It might be replacing one that's still waiting for its turn
+        if notification.replaces_id != 0 {
+            if let Some(pending) = pending_list
+                .iter_mut()
+                .find(|pending| pending.notification_id == notification.replaces_id)
+            {
+                *pending = notification;
+
+                return Ok(());
+            }
+        }
+
+        let group_key = group_key(&notification.app_name, &notification.desktop_entry);
 
-            list.insert(guid, _notification_widget);
+        // Joining a group doesn't take any extra room, so there's no need to wait
+        if !self.joins_group(&list, &group_key)
+            && (!pending_list.is_empty() || !self.has_room(&list, &group_key))
+        {
+            pending_list.push_back(notification);
 
             self.reorder();
-        };
+
+            return Ok(());
+        }
+
+        self.show_notification(&mut list, notification)
+    }
+
+    unsafe fn get_duplicate_notification<'a>(
+        self: &Rc<Self>,
+        list: &'a MutexGuard<LinkedHashMap<String, Rc<NotificationWidget>>>,
+        notification: &Notification,
+    ) -> Option<&'a Rc<NotificationWidget>> {
+        let duplicate_window = SETTINGS.duplicate_window.value;
+
+        if notification.replaces_id != 0 || duplicate_window <= 0 {
+            return None;
+        }
+
+        let group_key = group_key(&notification.app_name, &notification.desktop_entry);
+        let duplicate_window = Duration::from_millis(duplicate_window as u64);
+
+        list.values().find(|widget| {
+            *widget.group_key.borrow() == group_key
+                && *widget.summary.borrow() == notification.summary
+                && *widget.body.borrow() == notification.body
+                && widget.last_update.borrow().elapsed() <= duplicate_window
+        })
+    }
+
+    unsafe fn show_notification(
+        self: &Rc<Self>,
+        list: &mut MutexGuard<LinkedHashMap<String, Rc<NotificationWidget>>>,
+        notification: Notification,
+    ) -> Result<(), KrustifyError> {
+        let guid = Uuid::new_v4().to_string();
+
+        // A theme picked by a rule or the script has to be checked too
+        let theme = notification
+            .theme
+            .clone()
+            .filter(|theme| themes::check_theme(theme))
+            .unwrap_or_else(|| SETTINGS.theme.name.to_string());
+
+        let _notification_widget = NotificationWidget::new(
+            &self.main_window,
+            &self.close_signal,
+            &self.action_signal,
+            notification.notification_id,
+            guid.clone(),
+            &theme,
+            template::variants(&notification),
+        )?;
+
+        hooks::shown(notification.notification_id);
+
+        _notification_widget.silent.replace(notification.silent);
+        _notification_widget.set_expire_timeout(
+            SETTINGS
+                .durations
+                .expire_timeout(notification.timeout, notification.urgency),
+        );
+        _notification_widget.reset_timer();
+
+        // Stacks at the bottom grow upwards, so new notifications come in from below
+        if SETTINGS.position.is_bottom() {
+            _notification_widget
+                .widget
+                .move_2a(0, self.available_geometry().height());
+        }
+
+        self.set_notification_contents(notification, &_notification_widget);
+
+        self.check_hover
+            .connect(&_notification_widget.slot_check_hover());
+
+        list.insert(guid, _notification_widget);
+
+        self.reorder();
 
         Ok(())
     }
 
+    unsafe fn show_pending(
+        self: &Rc<Self>,
+        list: &mut MutexGuard<LinkedHashMap<String, Rc<NotificationWidget>>>,
+    ) {
+        let mut pending_list = self.pending_list.lock().expect("failed to acquire lock");
+
+        while let Some(notification) = pending_list.front() {
+            let group_key = group_key(&notification.app_name, &notification.desktop_entry);
+
+            if !self.has_room(list, &group_key) {
+                break;
+            }
+
+            let notification = pending_list.pop_front().expect("the front was just there");
+
+            self.show_notification(list, notification)
+                .expect("failed to spawn notification");
+        }
+    }
+
+    // Both in count and in the screen's height
+    unsafe fn has_room(
+        self: &Rc<Self>,
+        list: &MutexGuard<LinkedHashMap<String, Rc<NotificationWidget>>>,
+        group_key: &str,
+    ) -> bool {
+        if self.joins_group(list, group_key) {
+            return true;
+        }
+
+        let slots = Self::slots(list);
+
+        // There's always room for at least one
+        let last_widget = match slots.last() {
+            Some(slot) => slot[slot.len() - 1],
+            None => return true,
+        };
+
+        let max_notifications = SETTINGS.max_notifications.value;
+
+        if max_notifications > 0 && slots.len() as i32 >= max_notifications {
+            return false;
+        }
+
+        let stack_height: i32 = slots.iter().map(|slot| slot[slot.len() - 1].height()).sum();
+
+        // Every notification comes from the same template, so the last one is a good guess
+        stack_height + last_widget.height() <= self.max_stack_height()
+    }
+
+    unsafe fn joins_group(
+        self: &Rc<Self>,
+        list: &MutexGuard<LinkedHashMap<String, Rc<NotificationWidget>>>,
+        group_key: &str,
+    ) -> bool {
+        SETTINGS.group_notifications.value
+            && list
+                .values()
+                .any(|widget| widget.group_key.borrow().as_str() == group_key)
+    }
+
+    // Each slot has every widget of a group, oldest first, and the newest one is the group's card
+    unsafe fn slots(
+        list: &LinkedHashMap<String, Rc<NotificationWidget>>,
+    ) -> Vec<Vec<&Rc<NotificationWidget>>> {
+        let mut slots: Vec<Vec<&Rc<NotificationWidget>>> = Vec::new();
+
+        for widget in list.values() {
+            let slot = if SETTINGS.group_notifications.value {
+                slots
+                    .iter_mut()
+                    .find(|slot| *slot[0].group_key.borrow() == *widget.group_key.borrow())
+            } else {
+                None
+            };
+
+            match slot {
+                Some(slot) => slot.push(widget),
+                None => slots.push(vec![widget]),
+            }
+        }
+
+        slots
+    }
+
+    unsafe fn available_geometry(self: &Rc<Self>) -> CppBox<QRect> {
+        QApplication::desktop().available_geometry_int(self.screen_id)
+    }
+
+    unsafe fn max_stack_height(self: &Rc<Self>) -> i32 {
+        let available_geometry = self.available_geometry();
+
+        let overflow_indicator_height = match self.overflow_indicator.borrow().as_ref() {
+            Some(overflow_indicator) => overflow_indicator.widget.height(),
+            None => 0,
+        };
+
+        available_geometry.height() - overflow_indicator_height
+    }
+
     unsafe fn set_notification_contents(
         self: &Rc<Self>,
-        app_name: String,
-        image_data: Option<ImageData>,
-        image_path: Option<String>,
-        desktop_entry: String,
-        summary: String,
-        body: String,
+        notification: Notification,
         notification_widget: &Rc<NotificationWidget>,
     ) {
-        let icon = if !desktop_entry.is_empty() {
-            image_handler::find_icon(&desktop_entry)
+        let icon = if !notification.desktop_entry.is_empty() {
+            image_handler::find_icon(&notification.desktop_entry)
         } else {
-            image_handler::find_icon(&app_name)
+            image_handler::find_icon(&notification.app_name)
         };
 
-        if image_data.is_none() && image_path.is_none() {
+        notification_widget.set_metadata(&notification);
+
+        notification_widget.group_key.replace(group_key(
+            &notification.app_name,
+            &notification.desktop_entry,
+        ));
+
+        if *notification_widget.summary.borrow() != notification.summary
+            || *notification_widget.body.borrow() != notification.body
+        {
+            notification_widget.set_repeat_count(1);
+        }
+
+        notification_widget
+            .summary
+            .replace(notification.summary.clone());
+        notification_widget.body.replace(notification.body.clone());
+        notification_widget.last_update.replace(Instant::now());
+        notification_widget.set_arrived(QDateTime::current_date_time());
+
+        let app_name = notification.app_name;
+        let summary = notification.summary;
+        let body = notification.body;
+
+        if notification.image_data.is_none() && notification.image_path.is_none() {
             notification_widget.set_content_no_image(qs(app_name), qs(summary), qs(body), icon);
         } else {
-            let pixmap = if image_data.is_some() {
-                image_handler::parse_image(image_data.expect("literally the imposible"))
+            let pixmap = if let Some(image_data) = notification.image_data {
+                image_handler::parse_image(image_data)
             } else {
-                image_handler::load_image(image_path.expect("damn, stupid cosmic rays"))
+                image_handler::load_image(
+                    notification.image_path.expect("damn, stupid cosmic rays"),
+                )
             };
 
             notification_widget.set_content_with_image(
@@ -259,30 +498,105 @@ impl NotificationSpawner {
         let mut biggest_width = 0;
         let mut end_height = 0;
 
-        for widget in list.values() {
-            widget.animate_entry_signal.emit(height_accumulator);
-            height_accumulator += widget.widget.height();
-            biggest_width = if biggest_width < widget.widget.width() {
-                widget.widget.width()
-            } else {
-                biggest_width
-            };
+        let expanded = self.expanded.get();
+
+        let available_geometry = self.available_geometry();
+        let position = SETTINGS.position;
 
-            end_height = if height_accumulator < widget.widget.geometry().bottom() {
-                widget.widget.geometry().bottom()
+        // Where something `offset` pixels into the stack goes, counting from the screen edge
+        let stack_y = |offset: i32, height: i32| {
+            if position.is_bottom() {
+                available_geometry.height() - offset - height
             } else {
-                height_accumulator
+                offset
+            }
+        };
+
+        for slot in Self::slots(&list) {
+            let collapsed = slot.len() > 1 && !expanded;
+
+            for (index, widget) in slot.iter().enumerate() {
+                let is_card = index == slot.len() - 1;
+
+                // Collapsed widgets wait right under their card, so they expand from there
+                widget
+                    .animate_entry_signal
+                    .emit(stack_y(height_accumulator, widget.height()));
+                widget.set_group_count(if collapsed && is_card { slot.len() } else { 1 });
+
+                if collapsed && !is_card {
+                    widget.widget.hide();
+                    continue;
+                }
+
+                widget.widget.show();
+
+                height_accumulator += widget.height();
+                biggest_width = if biggest_width < widget.width() {
+                    widget.width()
+                } else {
+                    biggest_width
+                };
+
+                end_height = if height_accumulator < widget.widget.geometry().bottom() {
+                    widget.widget.geometry().bottom()
+                } else {
+                    height_accumulator
+                }
             }
         }
 
+        let pending_count = self
+            .pending_list
+            .lock()
+            .expect("failed to acquire lock")
+            .len();
+
+        let mut overflow_indicator = self.overflow_indicator.borrow_mut();
+
+        if pending_count == 0 {
+            if let Some(overflow_indicator) = overflow_indicator.take() {
+                overflow_indicator.widget.close();
+            }
+        } else {
+            if overflow_indicator.is_none() {
+                *overflow_indicator = OverflowIndicator::new(&self.main_window);
+            }
+
+            if let Some(overflow_indicator) = overflow_indicator.as_ref() {
+                overflow_indicator.set_count(pending_count);
+                overflow_indicator.widget.move_2a(
+                    0,
+                    stack_y(height_accumulator, overflow_indicator.widget.height()),
+                );
+                overflow_indicator.widget.show();
+
+                height_accumulator += overflow_indicator.widget.height();
+                biggest_width = biggest_width.max(overflow_indicator.widget.width());
+                end_height = end_height.max(height_accumulator);
+            }
+        }
+
+        // A stack at the bottom takes the whole height, so it doesn't have to move as it grows
+        let window_height = if position.is_bottom() {
+            available_geometry.height()
+        } else {
+            end_height
+        };
+
+        let window_x = if position.is_right() {
+            available_geometry.right() + 1 - biggest_width
+        } else {
+            available_geometry.x()
+        };
+
         self.main_window
-            .set_geometry_4a(0, 0, biggest_width, end_height);
-        let window_geometry = self.main_window.window().geometry();
+            .set_geometry_4a(0, 0, biggest_width, window_height);
         self.main_window.window().set_geometry_4a(
-            window_geometry.x(),
-            window_geometry.y(),
+            window_x,
+            available_geometry.y(),
             biggest_width,
-            end_height,
+            window_height,
         );
     }
 
@@ -299,18 +613,179 @@ impl NotificationSpawner {
     unsafe fn on_widget_close(self: &Rc<Self>, closed_widget: Ref<QString>) {
         let mut list = self.widget_list.lock().expect("failed to acquire lock");
 
-        let widget = list
-            .remove(&closed_widget.to_std_string())
-            .expect("failed to remove widget");
+        let closed_guid = closed_widget.to_std_string();
+
+        // It might have already been closed along with its group
+        let widget = match list.get(&closed_guid) {
+            Some(widget) => widget.clone(),
+            None => return,
+        };
+
+        let group_key = widget.group_key.borrow().clone();
+        let reason = widget.close_reason.take();
+        let dismissed = *widget.dismissed.borrow();
+
+        let group: Vec<String> = list
+            .iter()
+            .filter(|(_, other)| *other.group_key.borrow() == group_key)
+            .map(|(guid, _)| guid.clone())
+            .collect();
+
+        let closes_group = SETTINGS.group_notifications.value
+            && !self.expanded.get()
+            && group.len() > 1
+            && group.last() == Some(&closed_guid)
+            && dismissed;
+
+        list.remove(&closed_guid);
+        self.close_widget(&widget, reason);
+
+        // Dismissing a collapsed group's card dismisses everything under it
+        if closes_group {
+            for guid in group {
+                if let Some(member) = list.remove(&guid) {
+                    self.close_widget(&member, reason);
+                }
+            }
+        }
+
+        self.show_pending(&mut list);
+
+        self.reorder();
+    }
+
+    unsafe fn close_widget(self: &Rc<Self>, widget: &Rc<NotificationWidget>, reason: u32) {
         widget.widget.close();
         widget.overlay.close();
 
-        self.signal_sender
-            .send(DbusSignal::NotificationClosed {
-                notification_id: widget.notification_id.take(),
-                reason: widget.close_reason.take(),
-            })
-            .expect("failed to send signal");
+        theme_assets::release(&widget.theme);
+
+        // Every ID it was sent with is done, including the ones of duplicates folded into it
+        let earlier_ids = widget.earlier_ids.take();
+
+        for notification_id in earlier_ids
+            .into_iter()
+            .chain([widget.notification_id.take()])
+        {
+            self.signal_sender
+                .send(DbusSignal::NotificationClosed {
+                    notification_id,
+                    reason,
+                })
+                .expect("failed to send signal");
+        }
+    }
+
+    // Left Alt expands the groups and freezes the notifications
+    #[slot(SlotNoArgs)]
+    unsafe fn on_tick(self: &Rc<Self>) {
+        // There's nothing to expand or freeze, so the keyboard isn't asked
+        if self
+            .widget_list
+            .lock()
+            .expect("failed to acquire lock")
+            .is_empty()
+        {
+            return;
+        }
+
+        let held = self.device_state.get_keys().contains(&Keycode::LAlt);
+
+        if held != self.expanded.get() {
+            self.expanded.set(held);
+            self.reorder();
+        }
+
+        self.check_hover.emit(held);
+    }
+
+    #[slot(SlotNoArgs)]
+    unsafe fn on_check_scale(self: &Rc<Self>) {
+        let device_pixel_ratio = self.main_window.device_pixel_ratio_f();
+
+        if self.device_pixel_ratio.replace(device_pixel_ratio) == device_pixel_ratio {
+            return;
+        }
+
+        let list = self.widget_list.lock().expect("failed to acquire lock");
+
+        for widget in list.values() {
+            widget.rerender();
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.widget_list
+            .lock()
+            .expect("failed to acquire lock")
+            .is_empty()
+            && self
+                .pending_list
+                .lock()
+                .expect("failed to acquire lock")
+                .is_empty()
+    }
+
+    // The window deletes itself, so the spawner is unusable afterwards
+    pub unsafe fn close(&self) {
+        self.timer.stop();
+        self.main_window.window().close();
+    }
+
+    pub unsafe fn reload_theme(self: &Rc<Self>, theme: &str) {
+        self.rebuild(|widget| widget.theme == theme);
+    }
+
+    pub unsafe fn restyle(self: &Rc<Self>) {
+        self.rebuild(|_| true);
+    }
+
+    unsafe fn rebuild(self: &Rc<Self>, filter: impl Fn(&NotificationWidget) -> bool) {
+        let mut list = self.widget_list.lock().expect("failed to acquire lock");
+
+        let guids: Vec<String> = list
+            .iter()
+            .filter(|(_, widget)| filter(widget))
+            .map(|(guid, _)| guid.clone())
+            .collect();
+
+        for guid in guids {
+            let old_widget = list[&guid].clone();
+
+            let notification_widget = match NotificationWidget::new(
+                &self.main_window,
+                &self.close_signal,
+                &self.action_signal,
+                *old_widget.notification_id.borrow(),
+                guid.clone(),
+                &old_widget.theme,
+                old_widget.variants.clone(),
+            ) {
+                Ok(notification_widget) => notification_widget,
+                Err(err) => {
+                    eprintln!(
+                        "Could not rebuild notification with the new theme: {:?}",
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            notification_widget.take_over(&old_widget);
+
+            self.check_hover
+                .connect(&notification_widget.slot_check_hover());
+
+            old_widget.discard();
+
+            // Replaced in place, so it keeps its spot in the stack
+            *list.get_mut(&guid).expect("the widget was just there") = notification_widget;
+        }
+
+        // It's made again from the new template when it's needed
+        if let Some(overflow_indicator) = self.overflow_indicator.borrow_mut().take() {
+            overflow_indicator.widget.close();
+        }
 
         self.reorder();
     }
@@ -320,12 +795,29 @@ impl NotificationSpawner {
         let list = self.widget_list.lock().expect("failed to acquire lock");
 
         for widget in list.values() {
-            let _notification_id = widget.notification_id.borrow().to_owned();
-            if _notification_id as i32 == notification_id {
+            if widget.has_id(notification_id as u32) {
                 widget.close_reason.replace(3);
                 widget.on_close();
-                break;
+                return;
             }
         }
+
+        let mut pending_list = self.pending_list.lock().expect("failed to acquire lock");
+
+        if let Some(index) = pending_list
+            .iter()
+            .position(|pending| pending.notification_id as i32 == notification_id)
+        {
+            pending_list.remove(index);
+
+            self.signal_sender
+                .send(DbusSignal::NotificationClosed {
+                    notification_id: notification_id as u32,
+                    reason: 3,
+                })
+                .expect("failed to send signal");
+
+            self.reorder();
+        }
     }
 }
@@ -1,33 +1,74 @@
 pub mod notifications {
-    use std::ffi::{CStr, CString};
+    use std::ffi::CString;
+    use std::time::Instant;
     use std::{cell::RefCell, rc::Rc};
 
     use cpp_core::{CppBox, CppDeletable, Ptr, Ref, StaticUpcast};
-    use device_query::{DeviceQuery, DeviceState, Keycode};
 
     use crate::errors::KrustifyError;
+    use crate::image_handler::{self, Clip, Fit};
+    use crate::notification::Notification;
     use crate::settings::SETTINGS;
+    use crate::template::{easing_curve, load_template, AnimationStyle, Properties};
+    use crate::theme_assets;
     use qt_core::{
-        q_abstract_animation, q_io_device::OpenModeFlag, qs, slot, AspectRatioMode, ConnectionType,
-        GlobalColor, QBox, QByteArray, QEasingCurve, QFile, QFlags, QObject,
-        QParallelAnimationGroup, QPropertyAnimation, QPtr, QRect, QSequentialAnimationGroup,
-        QString, QVariant, SignalNoArgs, SignalOfInt, SignalOfQString, SlotNoArgs, SlotOfInt,
-        TextElideMode, TransformationMode, WidgetAttribute, WindowType,
+        q_abstract_animation, q_easing_curve::Type, qs, slot, ConnectionType, QBox, QByteArray,
+        QDateTime, QEasingCurve, QObject, QParallelAnimationGroup, QPauseAnimation,
+        QPropertyAnimation, QPtr, QRect, QSequentialAnimationGroup, QSize, QString, QTimer,
+        QVariant, SignalNoArgs, SignalOfInt, SignalOfQString, SlotNoArgs, SlotOfBool, SlotOfInt,
+        TextElideMode, WidgetAttribute, WindowType,
     };
-    use qt_gui::{q_painter::RenderHint, QColor, QCursor, QPainter, QPainterPath, QPixmap};
+    use qt_gui::{QColor, QCursor, QIcon, QPixmap};
     use qt_widgets::{
         QDialog, QFrame, QGraphicsBlurEffect, QGraphicsDropShadowEffect, QGraphicsOpacityEffect,
         QLabel, QPushButton, QStackedLayout, QWidget,
     };
 
+    // Set on the root widget and the frame, for stylesheets to select on
+    const STYLE_PROPERTIES: [&str; 8] = [
+        "urgency",
+        "category",
+        "appName",
+        "desktopEntry",
+        "hasImage",
+        "hasActions",
+        "hovered",
+        "frozen",
+    ];
+
+    // Kept so it can be shown again with another template
+    #[derive(Debug)]
+    struct Content {
+        app_name: String,
+        title: String,
+        body: String,
+        icon: CppBox<QIcon>,
+        image: Option<CppBox<QPixmap>>,
+    }
+
+    impl Content {
+        unsafe fn copy(&self) -> Content {
+            Content {
+                app_name: self.app_name.clone(),
+                title: self.title.clone(),
+                body: self.body.clone(),
+                icon: QIcon::new_copy(&self.icon),
+                image: self.image.as_ref().map(|image| QPixmap::new_copy(image)),
+            }
+        }
+    }
+
     #[derive(Debug)]
     pub struct NotificationWidget {
         pub widget: QBox<QWidget>,
         // Animations
         entry_animation: QBox<QPropertyAnimation>,
+        entry_fade_animation: QBox<QPropertyAnimation>,
         exit_animation: QBox<QPropertyAnimation>,
+        exit_geometry_animation: QBox<QPropertyAnimation>,
         blur_animation: QBox<QPropertyAnimation>,
         exit_animation_group: QBox<QSequentialAnimationGroup>,
+        pause_animation: QBox<QPauseAnimation>,
         parallel_animation: QBox<QParallelAnimationGroup>,
         // Content
         icon_label: QPtr<QLabel>,
@@ -35,26 +76,91 @@ pub mod notifications {
         image_label: QPtr<QLabel>,
         title_label: QPtr<QLabel>,
         body_label: QPtr<QLabel>,
+        group_count_label: QPtr<QLabel>,
+        group_count_text: String,
+        repeat_count_label: QPtr<QLabel>,
+        repeat_count_text: String,
+        time_label: QPtr<QLabel>,
+        time_text: String,
+        time_timer: QBox<QTimer>,
         close_signal: Ref<SignalOfQString>,
         pub animate_entry_signal: QBox<SignalOfInt>,
         blur_effect: QBox<QGraphicsBlurEffect>,
         opacity_effect: QBox<QGraphicsOpacityEffect>,
         action_button: QPtr<QPushButton>,
         pub notification_id: RefCell<u32>,
+        // IDs it had before taking over newer ones, which apps can still close it with
+        pub earlier_ids: RefCell<Vec<u32>>,
         pub overlay: QBox<QDialog>,
+        frame: QPtr<QFrame>,
         frame_shadow: QBox<QGraphicsDropShadowEffect>,
         action_signal: Ref<SignalOfInt>,
         guid: String,
         parallel_hover_animation: QBox<QParallelAnimationGroup>,
-        default_opacity: CppBox<QVariant>,
-        default_blur: CppBox<QVariant>,
-        end_blur: CppBox<QVariant>,
-        notification_duration: CppBox<QVariant>,
-        spawn_duration: CppBox<QVariant>,
-        disappear_duration: CppBox<QVariant>,
-        default_shadow_color: CppBox<QVariant>,
-        focused_shadow_color: CppBox<QVariant>,
+        pub properties: Properties,
         pub close_reason: RefCell<u32>,
+        // Clicked, rather than expired or closed by the app
+        pub dismissed: RefCell<bool>,
+        pub group_key: RefCell<String>,
+        pub summary: RefCell<String>,
+        pub body: RefCell<String>,
+        pub last_update: RefCell<Instant>,
+        pub repeat_count: RefCell<u32>,
+        // When it was sent, or last replaced
+        arrived: RefCell<CppBox<QDateTime>>,
+        expire_timeout: RefCell<i32>,
+        // Shows up in place instead of sliding in
+        pub silent: RefCell<bool>,
+        placed: RefCell<bool>,
+        reduced_motion: bool,
+        // Once it's done coming in or going away
+        size: CppBox<QSize>,
+        pub theme: String,
+        pub variants: Vec<String>,
+        content: RefCell<Option<Content>>,
+    }
+
+    unsafe fn easing(name: &str, fallback: Type) -> CppBox<QEasingCurve> {
+        QEasingCurve::new_1a(easing_curve(name).unwrap_or(fallback))
+    }
+
+    // Where a widget at `rect` comes in from, or goes away to
+    unsafe fn offstage(style: AnimationStyle, rect: &QRect) -> CppBox<QRect> {
+        let (x, y, width, height) = (rect.x(), rect.y(), rect.width(), rect.height());
+
+        match style {
+            AnimationStyle::SlideTop => QRect::from_4_int(x, y - height, width, height),
+            AnimationStyle::SlideBottom => QRect::from_4_int(x, y + height, width, height),
+            AnimationStyle::SlideLeft => QRect::from_4_int(x - width, y, width, height),
+            AnimationStyle::SlideRight => QRect::from_4_int(x + width, y, width, height),
+            // Half its size, around its center
+            AnimationStyle::Scale => {
+                QRect::from_4_int(x + width / 4, y + height / 4, width / 2, height / 2)
+            }
+            AnimationStyle::Fade | AnimationStyle::None => QRect::from_4_int(x, y, width, height),
+        }
+    }
+
+    // "now", then minutes ago for the first hour, then the time of day (and date if not today)
+    unsafe fn relative_time(arrived: &QDateTime) -> String {
+        let now = QDateTime::current_date_time();
+        let elapsed = arrived.secs_to(&now);
+
+        if elapsed < 60 {
+            String::from("now")
+        } else if elapsed < 60 * 60 {
+            format!("{} min ago", elapsed / 60)
+        } else if arrived.date().to_julian_day() == now.date().to_julian_day() {
+            arrived.to_string_q_string(&qs("HH:mm")).to_std_string()
+        } else {
+            arrived
+                .to_string_q_string(&qs("d MMM HH:mm"))
+                .to_std_string()
+        }
+    }
+
+    unsafe fn same_rect(a: &QRect, b: &QRect) -> bool {
+        a.x() == b.x() && a.y() == b.y() && a.width() == b.width() && a.height() == b.height()
     }
 
     impl StaticUpcast<QObject> for NotificationWidget {
@@ -70,6 +176,8 @@ pub mod notifications {
             action_signal: &QBox<SignalOfInt>,
             _notification_id: u32,
             guid: String,
+            theme: &str,
+            variants: Vec<String>,
         ) -> Result<Rc<NotificationWidget>, KrustifyError> {
             unsafe {
                 // Set the notification widget
@@ -87,38 +195,12 @@ pub mod notifications {
 
                 widget.set_layout(widget_layout.as_ptr());
 
-                let theme = &SETTINGS.theme.name;
-
-                let template_file =
-                    QFile::from_q_string(&qs(format!("./res/themes/{theme}/template.ui")));
-                template_file.open(QFlags::from(OpenModeFlag::ReadOnly));
-                let loader = qt_ui_tools::QUiLoader::new_1a(&widget);
-                let template = loader.load_1a(template_file.as_ptr());
-                template_file.reset();
-                template_file.close();
-                template_file.delete();
-                loader.delete();
-
-                // Load properties
-                let default_opacity =
-                    template.property(CStr::as_ptr(&CString::new("defaultOpacity")?));
-                let hovered_opacity =
-                    template.property(CStr::as_ptr(&CString::new("hoveredOpacity")?));
-                let default_blur = template.property(CStr::as_ptr(&CString::new("defaultBlur")?));
-                let hovered_blur = template.property(CStr::as_ptr(&CString::new("hoveredBlur")?));
-                let end_blur = template.property(CStr::as_ptr(&CString::new("endBlur")?));
-                let notification_duration =
-                    template.property(CStr::as_ptr(&CString::new("notificationDuration")?));
-                let spawn_duration =
-                    template.property(CStr::as_ptr(&CString::new("spawnDuration")?));
-                let disappear_duration =
-                    template.property(CStr::as_ptr(&CString::new("disappearDuration")?));
-                let default_shadow_color =
-                    template.property(CStr::as_ptr(&CString::new("defaultShadowColor")?));
-                let focused_shadow_color =
-                    template.property(CStr::as_ptr(&CString::new("focusedShadowColor")?));
-                let text_shadow_color =
-                    template.property(CStr::as_ptr(&CString::new("textShadowColor")?));
+                let template = load_template(theme, &variants)?;
+                let properties = template.properties.clone();
+                let template = template.build()?;
+
+                // The template's root goes away, so its stylesheet moves to the new root
+                widget.set_style_sheet(&template.style_sheet());
 
                 let notification: QPtr<QWidget> = template.find_child("notification")?;
 
@@ -153,11 +235,16 @@ pub mod notifications {
                 let opacity_effect = QGraphicsOpacityEffect::new_1a(&notification);
                 opacity_effect.set_object_name(&qs("opacity_effect"));
 
-                widget.set_graphics_effect(&blur_effect);
+                // Blur and shadows are left out when they'd be too slow
+                let reduced_motion = SETTINGS.reduced_motion.value;
+
+                if !reduced_motion {
+                    widget.set_graphics_effect(&blur_effect);
+                }
                 notification.set_graphics_effect(&opacity_effect);
 
-                opacity_effect.set_opacity(default_opacity.to_double_0a());
-                blur_effect.set_blur_radius(default_blur.to_double_0a());
+                opacity_effect.set_opacity(properties.default_opacity);
+                blur_effect.set_blur_radius(properties.default_blur);
 
                 widget.set_geometry_4a(
                     0,
@@ -166,7 +253,7 @@ pub mod notifications {
                     notification.geometry().height(),
                 );
 
-                widget.set_window_opacity(default_opacity.to_double_0a());
+                widget.set_window_opacity(properties.default_opacity);
 
                 // Set animations
                 let y_property = QByteArray::new();
@@ -180,8 +267,13 @@ pub mod notifications {
 
                 let entry_animation = QPropertyAnimation::new_2a(&widget, &y_property);
                 entry_animation.set_object_name(&qs("entry_animation"));
+                let entry_fade_animation =
+                    QPropertyAnimation::new_2a(&opacity_effect, &opacity_property);
+                entry_fade_animation.set_object_name(&qs("entry_fade_animation"));
                 let exit_animation = QPropertyAnimation::new_2a(&opacity_effect, &opacity_property);
                 exit_animation.set_object_name(&qs("exit_animation"));
+                let exit_geometry_animation = QPropertyAnimation::new_2a(&widget, &y_property);
+                exit_geometry_animation.set_object_name(&qs("exit_geometry_animation"));
                 let blur_animation =
                     QPropertyAnimation::new_2a(&blur_effect, &blur_radius_property);
                 blur_animation.set_object_name(&qs("blur_animation"));
@@ -193,17 +285,22 @@ pub mod notifications {
                 opacity_hover_animation.set_object_name(&qs("opacity_hover_animation"));
                 let exit_animation_group = QSequentialAnimationGroup::new_1a(&widget);
                 exit_animation_group.set_object_name(&qs("exit_animation_group"));
+                let pause_animation = QPauseAnimation::new_0a();
+                pause_animation.set_object_name(&qs("pause_animation"));
                 let parallel_animation = QParallelAnimationGroup::new_1a(&widget);
                 parallel_animation.set_object_name(&qs("parallel_animation"));
                 let parallel_hover_animation = QParallelAnimationGroup::new_1a(&widget);
                 parallel_hover_animation.set_object_name(&qs("parallel_hover_animation"));
 
-                blur_hover_animation.set_start_value(&default_blur);
-                blur_hover_animation.set_end_value(&hovered_blur);
-                blur_hover_animation.set_duration(100);
-                opacity_hover_animation.set_start_value(&default_opacity);
-                opacity_hover_animation.set_end_value(&hovered_opacity);
-                opacity_hover_animation.set_duration(100);
+                blur_hover_animation
+                    .set_start_value(&QVariant::from_double(properties.default_blur));
+                blur_hover_animation.set_end_value(&QVariant::from_double(properties.hovered_blur));
+                blur_hover_animation.set_duration(properties.hover_duration);
+                opacity_hover_animation
+                    .set_start_value(&QVariant::from_double(properties.default_opacity));
+                opacity_hover_animation
+                    .set_end_value(&QVariant::from_double(properties.hovered_opacity));
+                opacity_hover_animation.set_duration(properties.hover_duration);
 
                 parallel_hover_animation.add_animation(&blur_hover_animation);
                 parallel_hover_animation.add_animation(&opacity_hover_animation);
@@ -213,11 +310,13 @@ pub mod notifications {
                 let frame_shadow = QGraphicsDropShadowEffect::new_1a(&frame);
                 frame_shadow.set_object_name(&qs("frame_shadow"));
 
-                frame_shadow.set_blur_radius(10.0);
-                frame_shadow.set_x_offset(1.0);
-                frame_shadow.set_y_offset(1.0);
+                frame_shadow.set_blur_radius(properties.default_shadow_blur);
+                frame_shadow.set_x_offset(properties.shadow_offset);
+                frame_shadow.set_y_offset(properties.shadow_offset);
 
-                frame.set_graphics_effect(&frame_shadow);
+                if !reduced_motion {
+                    frame.set_graphics_effect(&frame_shadow);
+                }
 
                 // Set up content
                 let icon_label: QPtr<QLabel> =
@@ -225,15 +324,15 @@ pub mod notifications {
                 let app_name_label: QPtr<QLabel> =
                     widget.find_child("appNameLabel").unwrap_or(QPtr::null());
 
-                if !app_name_label.is_null() {
+                if !app_name_label.is_null() && !reduced_motion {
                     let app_name_label_shadow = QGraphicsDropShadowEffect::new_1a(&app_name_label);
                     app_name_label_shadow.set_object_name(&qs("app_name_label_shadow"));
 
-                    app_name_label_shadow.set_blur_radius(1.0);
+                    app_name_label_shadow.set_blur_radius(properties.text_shadow_blur);
                     app_name_label_shadow.set_x_offset(0.0);
                     app_name_label_shadow.set_y_offset(0.0);
                     app_name_label_shadow
-                        .set_color(&QColor::from_q_string(&text_shadow_color.to_string()));
+                        .set_color(&QColor::from_q_string(&qs(&properties.text_shadow_color)));
 
                     app_name_label.set_graphics_effect(&app_name_label_shadow);
                 }
@@ -244,16 +343,16 @@ pub mod notifications {
                 let title_label: QPtr<QLabel> =
                     widget.find_child("titleLabel").unwrap_or(QPtr::null());
 
-                if !title_label.is_null() {
+                if !title_label.is_null() && !reduced_motion {
                     let title_label_shadow = QGraphicsDropShadowEffect::new_1a(&title_label);
 
                     title_label_shadow.set_object_name(&qs("title_label_shadow"));
 
-                    title_label_shadow.set_blur_radius(1.0);
+                    title_label_shadow.set_blur_radius(properties.text_shadow_blur);
                     title_label_shadow.set_x_offset(0.0);
                     title_label_shadow.set_y_offset(0.0);
                     title_label_shadow
-                        .set_color(&QColor::from_q_string(&text_shadow_color.to_string()));
+                        .set_color(&QColor::from_q_string(&qs(&properties.text_shadow_color)));
 
                     title_label.set_graphics_effect(&title_label_shadow);
                 }
@@ -261,19 +360,62 @@ pub mod notifications {
                 let body_label: QPtr<QLabel> =
                     widget.find_child("bodyLabel").unwrap_or(QPtr::null());
 
-                if !body_label.is_null() {
+                if !body_label.is_null() && !reduced_motion {
                     let body_label_shadow = QGraphicsDropShadowEffect::new_1a(&body_label);
                     body_label_shadow.set_object_name(&qs("body_label_shadow"));
 
-                    body_label_shadow.set_blur_radius(1.0);
+                    body_label_shadow.set_blur_radius(properties.text_shadow_blur);
                     body_label_shadow.set_x_offset(0.0);
                     body_label_shadow.set_y_offset(0.0);
                     body_label_shadow
-                        .set_color(&QColor::from_q_string(&text_shadow_color.to_string()));
+                        .set_color(&QColor::from_q_string(&qs(&properties.text_shadow_color)));
 
                     body_label.set_graphics_effect(&body_label_shadow);
                 }
 
+                // Only shown on the card of a collapsed group
+                let group_count_label: QPtr<QLabel> =
+                    widget.find_child("groupCountLabel").unwrap_or(QPtr::null());
+
+                let group_count_text = if group_count_label.is_null() {
+                    String::new()
+                } else {
+                    group_count_label.hide();
+                    group_count_label.text().to_std_string()
+                };
+
+                // Only shown once the same notification has been sent more than once
+                let repeat_count_label: QPtr<QLabel> = widget
+                    .find_child("repeatCountLabel")
+                    .unwrap_or(QPtr::null());
+
+                let repeat_count_text = if repeat_count_label.is_null() {
+                    String::new()
+                } else {
+                    repeat_count_label.hide();
+                    repeat_count_label.text().to_std_string()
+                };
+
+                // Any {time} in it is replaced, otherwise it's just the time
+                let time_label: QPtr<QLabel> =
+                    widget.find_child("timeLabel").unwrap_or(QPtr::null());
+
+                let time_text = if time_label.is_null() {
+                    String::new()
+                } else {
+                    let text = time_label.text().to_std_string();
+
+                    if text.contains("{time}") {
+                        text
+                    } else {
+                        String::from("{time}")
+                    }
+                };
+
+                let time_timer = QTimer::new_1a(&widget);
+                time_timer.set_object_name(&qs("time_timer"));
+                time_timer.set_interval(10000);
+
                 let animate_entry_signal = SignalOfInt::new();
 
                 widget.show();
@@ -290,21 +432,33 @@ pub mod notifications {
 
                 let notification_id = RefCell::new(_notification_id);
 
+                let size = notification.geometry().size();
+
                 template.close();
                 template.delete();
 
                 let this = Rc::new(Self {
                     widget,
                     entry_animation,
+                    entry_fade_animation,
                     exit_animation,
+                    exit_geometry_animation,
                     blur_animation,
                     exit_animation_group,
+                    pause_animation,
                     parallel_animation,
                     icon_label,
                     app_name_label,
                     image_label,
                     title_label,
                     body_label,
+                    group_count_label,
+                    group_count_text,
+                    repeat_count_label,
+                    repeat_count_text,
+                    time_label,
+                    time_text,
+                    time_timer,
                     close_signal: close,
                     animate_entry_signal,
                     blur_effect,
@@ -312,20 +466,34 @@ pub mod notifications {
                     action_signal: action,
                     action_button,
                     notification_id,
+                    earlier_ids: RefCell::new(Vec::new()),
                     overlay,
+                    frame,
                     frame_shadow,
                     guid,
                     parallel_hover_animation,
-                    default_opacity,
-                    default_blur,
-                    end_blur,
-                    notification_duration,
-                    spawn_duration,
-                    disappear_duration,
-                    default_shadow_color,
-                    focused_shadow_color,
+                    properties,
                     close_reason: RefCell::new(1),
+                    dismissed: RefCell::new(false),
+                    group_key: RefCell::new(String::new()),
+                    summary: RefCell::new(String::new()),
+                    body: RefCell::new(String::new()),
+                    last_update: RefCell::new(Instant::now()),
+                    repeat_count: RefCell::new(1),
+                    arrived: RefCell::new(QDateTime::current_date_time()),
+                    expire_timeout: RefCell::new(-1),
+                    silent: RefCell::new(false),
+                    placed: RefCell::new(false),
+                    reduced_motion,
+                    size,
+                    theme: theme.to_string(),
+                    variants,
+                    content: RefCell::new(None),
                 });
+
+                // Its theme's fonts and resources stay registered while it's around
+                theme_assets::acquire(theme);
+
                 this.init();
                 this.animate_exit();
                 Ok(this)
@@ -350,7 +518,7 @@ pub mod notifications {
             app_name: CppBox<QString>,
             title: CppBox<QString>,
             body: CppBox<QString>,
-            icon: CppBox<QPixmap>,
+            icon: CppBox<QIcon>,
         ) {
             if !self.app_name_label.is_null() {
                 self.app_name_label.set_text(&app_name);
@@ -365,14 +533,21 @@ pub mod notifications {
             }
 
             if !self.icon_label.is_null() {
-                let scaled_icon = icon.scaled_2_int_aspect_ratio_mode_transformation_mode(
+                // With AA_UseHighDpiPixmaps, it comes in device pixels with its ratio set
+                let device_pixel_ratio = self.icon_label.device_pixel_ratio_f();
+                let pixmap = icon.pixmap_int(self.properties.icon_size);
+
+                let rendered_icon = image_handler::render(
+                    &pixmap,
                     self.icon_label.width(),
                     self.icon_label.height(),
-                    AspectRatioMode::IgnoreAspectRatio,
-                    TransformationMode::SmoothTransformation,
+                    Fit::from_name(&self.properties.icon_fit).unwrap_or(Fit::Contain),
+                    Clip::from_name(&self.properties.icon_clip).unwrap_or(Clip::None),
+                    self.properties.icon_roundness,
+                    device_pixel_ratio,
                 );
 
-                self.icon_label.set_pixmap(&scaled_icon);
+                self.icon_label.set_pixmap(&rendered_icon);
             }
 
             let signal = SignalNoArgs::new();
@@ -385,8 +560,16 @@ pub mod notifications {
             app_name: CppBox<QString>,
             title: CppBox<QString>,
             body: CppBox<QString>,
-            icon: CppBox<QPixmap>,
+            icon: CppBox<QIcon>,
         ) {
+            self.content.replace(Some(Content {
+                app_name: app_name.to_std_string(),
+                title: title.to_std_string(),
+                body: body.to_std_string(),
+                icon: QIcon::new_copy(&icon),
+                image: None,
+            }));
+
             self.set_content(app_name, title, body, icon);
         }
 
@@ -396,8 +579,16 @@ pub mod notifications {
             title: CppBox<QString>,
             body: CppBox<QString>,
             image: CppBox<QPixmap>,
-            icon: CppBox<QPixmap>,
+            icon: CppBox<QIcon>,
         ) {
+            self.content.replace(Some(Content {
+                app_name: app_name.to_std_string(),
+                title: title.to_std_string(),
+                body: body.to_std_string(),
+                icon: QIcon::new_copy(&icon),
+                image: Some(QPixmap::new_copy(&image)),
+            }));
+
             if !self.image_label.is_null() {
                 let scaled_image = self.resize_image(image);
 
@@ -417,58 +608,296 @@ pub mod notifications {
         }
 
         unsafe fn resize_image(self: &Rc<Self>, pixmap: CppBox<QPixmap>) -> CppBox<QPixmap> {
-            let target = QPixmap::from_2_int(
+            image_handler::render(
+                &pixmap,
                 self.image_label.maximum_height(),
                 self.image_label.maximum_height(),
-            );
+                Fit::from_name(&self.properties.image_fit).unwrap_or(Fit::Cover),
+                Clip::from_name(&self.properties.image_clip).unwrap_or(Clip::Rounded),
+                self.properties.image_roundness,
+                self.image_label.device_pixel_ratio_f(),
+            )
+        }
 
-            target.fill_1a(&QColor::from_global_color(GlobalColor::Transparent));
+        // After its screen's scale changed
+        pub unsafe fn rerender(self: &Rc<Self>) {
+            let content = match self.content.borrow().as_ref() {
+                Some(content) => content.copy(),
+                None => return,
+            };
 
-            let painter = QPainter::new_1a(&target);
+            self.show_content(content);
+        }
 
-            painter.set_render_hints_2a(
-                RenderHint::HighQualityAntialiasing
-                    | RenderHint::SmoothPixmapTransform
-                    | RenderHint::Antialiasing,
-                true,
-            );
+        unsafe fn show_content(self: &Rc<Self>, content: Content) {
+            match content.image {
+                Some(image) => self.set_content_with_image(
+                    qs(&content.app_name),
+                    qs(&content.title),
+                    qs(&content.body),
+                    image,
+                    content.icon,
+                ),
+                None => self.set_content_no_image(
+                    qs(&content.app_name),
+                    qs(&content.title),
+                    qs(&content.body),
+                    content.icon,
+                ),
+            }
+        }
 
-            let path = QPainterPath::new_0a();
-            path.add_round_rect_6a(
-                0.0,
-                0.0,
-                self.image_label.maximum_height() as f64,
-                self.image_label.maximum_height() as f64,
-                25,
-                25,
+        pub unsafe fn set_metadata(self: &Rc<Self>, notification: &Notification) {
+            let urgency = match notification.urgency {
+                0 => "low",
+                2 => "critical",
+                _ => "normal",
+            };
+
+            self.set_style_property("urgency", &QVariant::from_q_string(&qs(urgency)));
+            self.set_style_property(
+                "category",
+                &QVariant::from_q_string(&qs(&notification.category)),
+            );
+            self.set_style_property(
+                "appName",
+                &QVariant::from_q_string(&qs(&notification.app_name)),
+            );
+            self.set_style_property(
+                "desktopEntry",
+                &QVariant::from_q_string(&qs(&notification.desktop_entry)),
+            );
+            self.set_style_property(
+                "hasImage",
+                &QVariant::from_bool(
+                    notification.image_data.is_some() || notification.image_path.is_some(),
+                ),
+            );
+            self.set_style_property(
+                "hasActions",
+                &QVariant::from_bool(!notification.actions.is_empty()),
             );
 
-            painter.set_clip_path_1a(&path);
+            self.repolish(true);
+        }
 
-            let scaled_pixmap = pixmap.scaled_2_int_aspect_ratio_mode_transformation_mode(
-                self.image_label.maximum_height(),
-                self.image_label.maximum_height(),
-                AspectRatioMode::IgnoreAspectRatio,
-                TransformationMode::SmoothTransformation,
+        unsafe fn set_style_property(self: &Rc<Self>, name: &str, value: &CppBox<QVariant>) {
+            let name = CString::new(name).expect("property names have no nul bytes");
+
+            self.widget.set_property(name.as_ptr(), value);
+            self.frame.set_property(name.as_ptr(), value);
+        }
+
+        // Hovered and frozen are checked all the time, so it's only restyled when they change
+        unsafe fn set_state(self: &Rc<Self>, name: &str, value: bool) {
+            let current = self.widget.property(
+                CString::new(name)
+                    .expect("property names have no nul bytes")
+                    .as_ptr(),
             );
 
-            painter.draw_pixmap_q_rect_q_pixmap(&target.rect(), &scaled_pixmap);
+            if current.is_valid() && current.to_bool() == value {
+                return;
+            }
+
+            self.set_style_property(name, &QVariant::from_bool(value));
+            self.repolish(false);
+        }
+
+        // Stylesheets don't notice property changes on their own. The labels only need it for
+        // the metadata, which themes use in selectors like `[urgency="critical"] QLabel`
+        unsafe fn repolish(self: &Rc<Self>, labels: bool) {
+            let mut widgets = vec![
+                self.widget.as_ptr(),
+                self.frame.as_ptr().static_upcast::<QWidget>(),
+            ];
+
+            if labels {
+                for label in [
+                    &self.icon_label,
+                    &self.app_name_label,
+                    &self.image_label,
+                    &self.title_label,
+                    &self.body_label,
+                    &self.group_count_label,
+                    &self.repeat_count_label,
+                    &self.time_label,
+                ] {
+                    if !label.is_null() {
+                        widgets.push(label.as_ptr().static_upcast::<QWidget>());
+                    }
+                }
+            }
+
+            for widget in widgets {
+                widget.style().unpolish_q_widget(widget);
+                widget.style().polish_q_widget(widget);
+                widget.update();
+            }
+        }
+
+        pub unsafe fn set_group_count(self: &Rc<Self>, count: usize) {
+            if self.group_count_label.is_null() {
+                return;
+            }
+
+            if count > 1 {
+                self.group_count_label.set_text(&qs(self
+                    .group_count_text
+                    .replace("{count}", &count.to_string())));
+                self.group_count_label.show();
+            } else {
+                self.group_count_label.hide();
+            }
+        }
+
+        pub unsafe fn set_repeat_count(self: &Rc<Self>, count: u32) {
+            self.repeat_count.replace(count);
+
+            if self.repeat_count_label.is_null() {
+                return;
+            }
+
+            if count > 1 {
+                self.repeat_count_label.set_text(&qs(self
+                    .repeat_count_text
+                    .replace("{count}", &count.to_string())));
+                self.repeat_count_label.show();
+            } else {
+                self.repeat_count_label.hide();
+            }
+        }
+
+        pub fn has_id(&self, notification_id: u32) -> bool {
+            *self.notification_id.borrow() == notification_id
+                || self.earlier_ids.borrow().contains(&notification_id)
+        }
+
+        // Actions are sent with the ID the client just got back, and the old one still closes it
+        pub fn take_id(&self, notification_id: u32) {
+            let earlier_id = self.notification_id.replace(notification_id);
+
+            if earlier_id != notification_id {
+                self.earlier_ids.borrow_mut().push(earlier_id);
+            }
+        }
+
+        pub unsafe fn set_arrived(self: &Rc<Self>, arrived: CppBox<QDateTime>) {
+            self.arrived.replace(arrived);
 
-            target
+            self.update_time();
+        }
+
+        #[slot(SlotNoArgs)]
+        unsafe fn update_time(self: &Rc<Self>) {
+            if self.time_label.is_null() {
+                return;
+            }
+
+            let arrived = self.arrived.borrow();
+
+            let time = match SETTINGS.time_format.format.as_str() {
+                "relative" => relative_time(&arrived),
+                format => arrived.to_string_q_string(&qs(format)).to_std_string(),
+            };
+
+            self.time_label
+                .set_text(&qs(self.time_text.replace("{time}", &time)));
+        }
+
+        // Never if 0, and the theme's notificationDuration if negative
+        pub unsafe fn set_expire_timeout(self: &Rc<Self>, expire_timeout: i32) {
+            self.expire_timeout.replace(expire_timeout);
+
+            if expire_timeout > 0 {
+                self.pause_animation.set_duration(expire_timeout);
+            } else {
+                self.pause_animation
+                    .set_duration(self.properties.notification_duration);
+            }
         }
 
         pub unsafe fn reset_timer(self: &Rc<Self>) {
             self.exit_animation_group.set_current_time(0);
-            self.exit_animation_group.start_0a();
+
+            if *self.expire_timeout.borrow() == 0 {
+                self.exit_animation_group.stop();
+            } else {
+                self.exit_animation_group.start_0a();
+            }
         }
 
-        #[slot(SlotNoArgs)]
-        pub unsafe fn check_hover(self: &Rc<Self>) {
-            let device_state = DeviceState::new();
+        // Replaces one built from an older version of the template, keeping its spot and time left
+        pub unsafe fn take_over(self: &Rc<Self>, old: &Rc<NotificationWidget>) {
+            self.close_reason.replace(*old.close_reason.borrow());
+            self.earlier_ids.replace(old.earlier_ids.borrow().clone());
+            self.group_key.replace(old.group_key.borrow().clone());
+            self.summary.replace(old.summary.borrow().clone());
+            self.body.replace(old.body.borrow().clone());
+            self.last_update.replace(*old.last_update.borrow());
+            self.set_arrived(QDateTime::new_copy(&*old.arrived.borrow()));
+            self.silent.replace(*old.silent.borrow());
+            self.placed.replace(true);
+            self.set_repeat_count(*old.repeat_count.borrow());
+
+            for name in STYLE_PROPERTIES {
+                let value = old.widget.property(
+                    CString::new(name)
+                        .expect("property names have no nul bytes")
+                        .as_ptr(),
+                );
+
+                if value.is_valid() {
+                    self.set_style_property(name, &value);
+                }
+            }
+
+            self.repolish(true);
+
+            if let Some(content) = old.content.borrow().as_ref() {
+                self.show_content(content.copy());
+            }
+
+            // It moves from where the old one was to wherever the stack puts it now
+            self.widget.set_geometry_4a(
+                old.widget.x(),
+                old.widget.y(),
+                self.widget.width(),
+                self.widget.height(),
+            );
+            self.widget.set_visible(old.widget.is_visible());
 
-            let keys: Vec<Keycode> = device_state.get_keys();
+            self.set_expire_timeout(*old.expire_timeout.borrow());
 
-            if keys.contains(&Keycode::LAlt) {
+            let current_time = old.exit_animation_group.current_time();
+
+            match old.exit_animation_group.state() {
+                q_abstract_animation::State::Running => {
+                    self.exit_animation_group.start_0a();
+                    self.exit_animation_group.set_current_time(current_time);
+                }
+                q_abstract_animation::State::Paused => {
+                    self.exit_animation_group.start_0a();
+                    self.exit_animation_group.set_current_time(current_time);
+                    self.exit_animation_group.pause();
+                }
+                _ => self.exit_animation_group.stop(),
+            }
+        }
+
+        // Without it counting as closed
+        pub unsafe fn discard(self: &Rc<Self>) {
+            self.exit_animation_group.stop();
+            self.widget.close();
+            self.overlay.close();
+
+            theme_assets::release(&self.theme);
+        }
+
+        // The spawner reads Left Alt once for all of them
+        #[slot(SlotOfBool)]
+        pub unsafe fn check_hover(self: &Rc<Self>, frozen: bool) {
+            if frozen {
                 self.freeze();
             } else {
                 self.unfreeze();
@@ -490,17 +919,24 @@ pub mod notifications {
         }
 
         pub unsafe fn hover(self: &Rc<Self>) {
+            self.set_state("hovered", true);
+
             if self.overlay.is_visible() {
                 self.blur_effect
-                    .set_blur_radius(self.default_blur.to_double_0a());
-                self.opacity_effect.set_opacity(0.99); // For some reason setting it to 1.0 shifts the widget slightly to the bottom-right
-                self.frame_shadow.set_blur_radius(15.0);
+                    .set_blur_radius(self.properties.default_blur);
+                self.opacity_effect
+                    .set_opacity(self.properties.focused_opacity);
+                self.frame_shadow
+                    .set_blur_radius(self.properties.focused_shadow_blur);
 
-                let color = QColor::from_q_string(&self.focused_shadow_color.to_string());
+                let color = QColor::from_q_string(&qs(&self.properties.focused_shadow_color));
 
                 self.frame_shadow.set_color(&color);
                 self.frame_shadow.set_offset_2_double(0.0, 0.0);
-            } else if self.exit_animation.state() != q_abstract_animation::State::Running {
+            } else if self.reduced_motion {
+                self.opacity_effect
+                    .set_opacity(self.properties.hovered_opacity);
+            } else if self.parallel_animation.state() != q_abstract_animation::State::Running {
                 self.parallel_hover_animation
                     .set_direction(q_abstract_animation::Direction::Forward);
 
@@ -513,12 +949,17 @@ pub mod notifications {
         }
 
         pub unsafe fn unhover(self: &Rc<Self>) {
+            self.set_state("hovered", false);
+
             if self.overlay.is_visible() {
                 self.blur_effect
-                    .set_blur_radius(self.default_blur.to_double_0a());
+                    .set_blur_radius(self.properties.default_blur);
+                self.opacity_effect
+                    .set_opacity(self.properties.default_opacity);
+            } else if self.reduced_motion {
                 self.opacity_effect
-                    .set_opacity(self.default_opacity.to_double_0a());
-            } else if self.exit_animation.state() != q_abstract_animation::State::Running {
+                    .set_opacity(self.properties.default_opacity);
+            } else if self.parallel_animation.state() != q_abstract_animation::State::Running {
                 if self.parallel_hover_animation.state() == q_abstract_animation::State::Stopped
                     && self.parallel_hover_animation.current_time() > 0
                 {
@@ -528,27 +969,53 @@ pub mod notifications {
                 }
             }
 
-            self.frame_shadow.set_blur_radius(10.0);
+            self.frame_shadow
+                .set_blur_radius(self.properties.default_shadow_blur);
 
-            let color = QColor::from_q_string(&self.default_shadow_color.to_string());
+            let color = QColor::from_q_string(&qs(&self.properties.default_shadow_color));
 
             self.frame_shadow.set_color(&color);
-            self.frame_shadow.set_offset_2_double(1.0, 1.0);
+            self.frame_shadow
+                .set_offset_2_double(self.properties.shadow_offset, self.properties.shadow_offset);
         }
 
         #[slot(SlotOfInt)]
         pub unsafe fn animate_entry(self: &Rc<Self>, height: i32) {
-            self.entry_animation
-                .set_duration(self.spawn_duration.to_int_0a());
-
             let start_value = self.widget.geometry();
-            let end_value = QRect::from_4_int(
-                start_value.left(),
-                height,
-                start_value.width(),
-                start_value.height(),
-            );
+            let end_value = QRect::from_4_int(0, height, self.size.width(), self.size.height());
+
+            let first_placement = !self.placed.replace(true);
 
+            if first_placement {
+                self.enter(start_value, end_value);
+
+                return;
+            }
+
+            if self.reduced_motion {
+                self.widget.set_geometry_1a(&end_value);
+
+                return;
+            }
+
+            // Whatever is going away doesn't make room for anything
+            if self.exit_geometry_animation.state() == q_abstract_animation::State::Running {
+                return;
+            }
+
+            // Reordering again while it's on its way keeps it going where it was
+            if self.entry_animation.state() == q_abstract_animation::State::Running {
+                if same_rect(&self.entry_animation.end_value().to_rect(), &end_value) {
+                    return;
+                }
+            } else if same_rect(&start_value, &end_value) {
+                return;
+            }
+
+            self.entry_animation
+                .set_duration(self.properties.move_duration);
+            self.entry_animation
+                .set_easing_curve(&easing(&self.properties.move_easing, Type::OutCubic));
             self.entry_animation
                 .set_start_value(&QVariant::from_q_rect(start_value));
             self.entry_animation
@@ -556,29 +1023,114 @@ pub mod notifications {
             self.entry_animation.start_0a();
         }
 
+        // The first time the stack places it
+        unsafe fn enter(self: &Rc<Self>, start_value: CppBox<QRect>, end_value: CppBox<QRect>) {
+            let style = AnimationStyle::from_name(&self.properties.entry_animation)
+                .unwrap_or(AnimationStyle::SlideTop);
+
+            if *self.silent.borrow() || self.reduced_motion || style == AnimationStyle::None {
+                self.widget.set_geometry_1a(&end_value);
+
+                return;
+            }
+
+            if style == AnimationStyle::Fade {
+                self.widget.set_geometry_1a(&end_value);
+
+                self.entry_fade_animation
+                    .set_duration(self.properties.spawn_duration);
+                self.entry_fade_animation
+                    .set_easing_curve(&easing(&self.properties.entry_easing, Type::Linear));
+                self.entry_fade_animation
+                    .set_start_value(&QVariant::from_double(0.0));
+                self.entry_fade_animation
+                    .set_end_value(&QVariant::from_double(self.properties.default_opacity));
+                self.entry_fade_animation.start_0a();
+
+                return;
+            }
+
+            // Sliding from the top starts above the window, where new widgets are made
+            let start_value = if style == AnimationStyle::SlideTop {
+                start_value
+            } else {
+                offstage(style, &end_value)
+            };
+
+            self.widget.set_geometry_1a(&start_value);
+
+            self.entry_animation
+                .set_duration(self.properties.spawn_duration);
+            self.entry_animation
+                .set_easing_curve(&easing(&self.properties.entry_easing, Type::Linear));
+            self.entry_animation
+                .set_start_value(&QVariant::from_q_rect(&start_value));
+            self.entry_animation
+                .set_end_value(&QVariant::from_q_rect(&end_value));
+            self.entry_animation.start_0a();
+        }
+
+        fn exit_style(&self) -> AnimationStyle {
+            if self.reduced_motion {
+                return AnimationStyle::None;
+            }
+
+            AnimationStyle::from_name(&self.properties.exit_animation)
+                .unwrap_or(AnimationStyle::Fade)
+        }
+
+        // Even while it's scaling in or out
+        pub unsafe fn height(&self) -> i32 {
+            self.size.height()
+        }
+
+        // Even while it's scaling in or out
+        pub unsafe fn width(&self) -> i32 {
+            self.size.width()
+        }
+
         #[slot(SlotNoArgs)]
         unsafe fn animate_exit(self: &Rc<Self>) {
             self.exit_animation
-                .set_duration(self.disappear_duration.to_int_0a());
-            self.exit_animation.set_start_value(&self.default_opacity);
+                .set_duration(self.properties.disappear_duration);
+            self.exit_animation
+                .set_start_value(&QVariant::from_double(self.properties.default_opacity));
             self.exit_animation
                 .set_end_value(&QVariant::from_float(0.0));
-            self.exit_animation.set_easing_curve(&QEasingCurve::new_1a(
-                qt_core::q_easing_curve::Type::OutCurve,
-            ));
+            self.exit_animation
+                .set_easing_curve(&easing(&self.properties.exit_easing, Type::OutCurve));
+
+            self.exit_geometry_animation
+                .set_duration(self.properties.disappear_duration);
+            self.exit_geometry_animation
+                .set_easing_curve(&easing(&self.properties.exit_easing, Type::OutCurve));
 
             self.blur_animation
-                .set_duration(self.disappear_duration.to_int_0a());
-            self.blur_animation.set_start_value(&self.default_blur);
-            self.blur_animation.set_end_value(&self.end_blur);
+                .set_duration(self.properties.disappear_duration);
+            self.blur_animation
+                .set_start_value(&QVariant::from_double(self.properties.default_blur));
+            self.blur_animation
+                .set_end_value(&QVariant::from_double(self.properties.end_blur));
 
-            self.parallel_animation.add_animation(&self.blur_animation);
-            self.parallel_animation.add_animation(&self.exit_animation);
+            match self.exit_style() {
+                AnimationStyle::Fade => {
+                    self.parallel_animation.add_animation(&self.blur_animation);
+                    self.parallel_animation.add_animation(&self.exit_animation);
+                }
+                // Nothing to animate, so it closes as soon as it's time
+                AnimationStyle::None => {}
+                _ => self
+                    .parallel_animation
+                    .add_animation(&self.exit_geometry_animation),
+            }
 
-            self.exit_animation_group
-                .add_pause(self.notification_duration.to_int_0a())
+            self.pause_animation
+                .set_duration(self.properties.notification_duration);
+            self.pause_animation
                 .finished()
                 .connect(&self.slot_on_init_exit());
+            self.exit_animation_group
+                .add_animation(&self.pause_animation);
             self.exit_animation_group
                 .add_animation(&self.parallel_animation);
 
@@ -595,6 +1147,12 @@ pub mod notifications {
             self.action_button
                 .clicked()
                 .connect(&self.slot_on_button_clicked());
+
+            if !self.time_label.is_null() {
+                self.time_timer.timeout().connect(&self.slot_update_time());
+                self.time_timer.start_0a();
+                self.update_time();
+            }
         }
 
         #[slot(SlotNoArgs)]
@@ -605,6 +1163,21 @@ pub mod notifications {
         #[slot(SlotNoArgs)]
         unsafe fn on_init_exit(self: &Rc<Self>) {
             self.parallel_hover_animation.stop();
+            self.entry_fade_animation.stop();
+
+            let style = self.exit_style();
+
+            if style.changes_geometry() {
+                self.entry_animation.stop();
+
+                let start_value = self.widget.geometry();
+
+                self.exit_geometry_animation
+                    .set_end_value(&QVariant::from_q_rect(&offstage(style, &start_value)));
+                self.exit_geometry_animation
+                    .set_start_value(&QVariant::from_q_rect(&start_value));
+            }
+
             self.exit_animation
                 .set_start_value(&qt_core::QVariant::from_double(
                     self.opacity_effect.opacity(),
@@ -633,14 +1206,16 @@ pub mod notifications {
                 return;
             }
             self.overlay.set_visible(true);
-            if self.exit_animation_group.state() == q_abstract_animation::State::Paused {
+            self.set_state("frozen", true);
+            // Persistent notifications have no timer to pause
+            if self.exit_animation_group.state() != q_abstract_animation::State::Running {
                 return;
             }
             self.exit_animation_group.pause();
             self.blur_effect
-                .set_blur_radius(self.default_blur.to_double_0a());
+                .set_blur_radius(self.properties.default_blur);
             self.opacity_effect
-                .set_opacity(self.default_opacity.to_double_0a());
+                .set_opacity(self.properties.default_opacity);
         }
 
         unsafe fn unfreeze(self: &Rc<Self>) {
@@ -648,12 +1223,15 @@ pub mod notifications {
                 return;
             }
             self.overlay.set_visible(false);
-            self.frame_shadow.set_blur_radius(10.0);
+            self.set_state("frozen", false);
+            self.frame_shadow
+                .set_blur_radius(self.properties.default_shadow_blur);
 
-            let color = QColor::from_q_string(&self.default_shadow_color.to_string());
+            let color = QColor::from_q_string(&qs(&self.properties.default_shadow_color));
 
             self.frame_shadow.set_color(&color);
-            self.frame_shadow.set_offset_2_double(1.0, 1.0);
+            self.frame_shadow
+                .set_offset_2_double(self.properties.shadow_offset, self.properties.shadow_offset);
             self.parallel_hover_animation.set_current_time(0);
             if self.exit_animation_group.state() != q_abstract_animation::State::Paused {
                 return;
@@ -665,6 +1243,7 @@ pub mod notifications {
         unsafe fn on_button_clicked(self: &Rc<Self>) {
             let notification_id = self.notification_id.borrow().to_owned();
             self.action_signal.emit(notification_id as i32);
+            self.dismissed.replace(true);
             self.on_close();
         }
     }
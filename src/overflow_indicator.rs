@@ -0,0 +1,80 @@
+use cpp_core::CppDeletable;
+use qt_core::{qs, QBox, QPtr, WidgetAttribute};
+use qt_widgets::{QFrame, QLabel, QStackedLayout, QWidget};
+
+use crate::settings::SETTINGS;
+use crate::template::{load_template, TEMPLATE_FILE};
+
+// Shown under the stack while notifications are waiting for room, if the theme has one
+pub struct OverflowIndicator {
+    pub widget: QBox<QWidget>,
+    label: QPtr<QLabel>,
+    label_text: String,
+}
+
+impl OverflowIndicator {
+    pub fn new(main_window: &QBox<QFrame>) -> Option<OverflowIndicator> {
+        unsafe {
+            let template = match load_template(&SETTINGS.theme.name, &[String::from(TEMPLATE_FILE)])
+                .and_then(|template| template.build())
+            {
+                Ok(template) => template,
+                Err(_) => return None,
+            };
+
+            let overflow_widget: QPtr<QWidget> = match template.find_child("overflowIndicator") {
+                Ok(overflow_widget) => overflow_widget,
+                Err(_) => {
+                    template.delete();
+                    return None;
+                }
+            };
+
+            let widget = QWidget::new_1a(main_window);
+
+            widget.set_object_name(&qs("overflow_indicator"));
+            widget.set_attribute_1a(WidgetAttribute::WATranslucentBackground);
+            widget.set_attribute_1a(WidgetAttribute::WADeleteOnClose);
+            widget.set_attribute_1a(WidgetAttribute::WANoSystemBackground);
+
+            let widget_layout = QStackedLayout::new();
+
+            widget.set_layout(widget_layout.as_ptr());
+            widget.set_style_sheet(&template.style_sheet());
+            widget.layout().add_widget(&overflow_widget);
+
+            widget.set_geometry_4a(
+                0,
+                0,
+                overflow_widget.geometry().width(),
+                overflow_widget.geometry().height(),
+            );
+
+            // The label's text in the template is used as a format, e.g. "+{count} more"
+            let label: QPtr<QLabel> = overflow_widget
+                .find_child("overflowLabel")
+                .unwrap_or(QPtr::null());
+
+            let label_text = if label.is_null() {
+                String::new()
+            } else {
+                label.text().to_std_string()
+            };
+
+            template.delete();
+
+            Some(OverflowIndicator {
+                widget,
+                label,
+                label_text,
+            })
+        }
+    }
+
+    pub unsafe fn set_count(&self, count: usize) {
+        if !self.label.is_null() {
+            self.label
+                .set_text(&qs(self.label_text.replace("{count}", &count.to_string())));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// How many notifications can be sent in a row
+    pub burst: f64,
+    /// How fast the allowance comes back
+    pub per_second: f64,
+}
+
+#[derive(Debug)]
+pub enum Verdict {
+    Allow,
+    /// `warn` is only set for the first one dropped since the app started flooding
+    Drop {
+        warn: bool,
+    },
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    warned: bool,
+    rate_limit: RateLimit,
+}
+
+impl TokenBucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        (self.tokens + elapsed * self.rate_limit.per_second).min(self.rate_limit.burst)
+    }
+}
+
+/// A token bucket per application, so one flooding notifications can't drown everyone else's.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn check(&mut self, app: &str, rate_limit: RateLimit) -> Verdict {
+        self.check_at(app, rate_limit, Instant::now())
+    }
+
+    fn check_at(&mut self, app: &str, rate_limit: RateLimit, now: Instant) -> Verdict {
+        // A full bucket is the same as a new one, so apps that went quiet aren't kept around
+        self.buckets
+            .retain(|_, bucket| bucket.refilled(now) < bucket.rate_limit.burst);
+
+        let bucket = self.buckets.entry(app.to_string()).or_insert(TokenBucket {
+            tokens: rate_limit.burst,
+            last_refill: now,
+            warned: false,
+            rate_limit,
+        });
+
+        bucket.rate_limit = rate_limit;
+        bucket.tokens = bucket.refilled(now);
+        bucket.last_refill = now;
+
+        // The flood is over once the bucket has filled back up
+        if bucket.tokens >= rate_limit.burst {
+            bucket.warned = false;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            Verdict::Allow
+        } else {
+            let warn = !bucket.warned;
+
+            bucket.warned = true;
+
+            Verdict::Drop { warn }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 3.0,
+        per_second: 1.0,
+    };
+
+    fn allowed(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Allow)
+    }
+
+    #[test]
+    fn allows_a_burst_then_drops() {
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(allowed(rate_limiter.check_at("app", LIMIT, now)));
+        }
+
+        assert!(!allowed(rate_limiter.check_at("app", LIMIT, now)));
+    }
+
+    #[test]
+    fn apps_have_their_own_buckets() {
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            rate_limiter.check_at("flood", LIMIT, now);
+        }
+
+        assert!(!allowed(rate_limiter.check_at("flood", LIMIT, now)));
+        assert!(allowed(rate_limiter.check_at("quiet", LIMIT, now)));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            rate_limiter.check_at("app", LIMIT, now);
+        }
+
+        let later = now + Duration::from_millis(1500);
+
+        assert!(allowed(rate_limiter.check_at("app", LIMIT, later)));
+        assert!(!allowed(rate_limiter.check_at("app", LIMIT, later)));
+    }
+
+    #[test]
+    fn warns_once_per_flood() {
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            rate_limiter.check_at("app", LIMIT, now);
+        }
+
+        assert!(matches!(
+            rate_limiter.check_at("app", LIMIT, now),
+            Verdict::Drop { warn: true }
+        ));
+        assert!(matches!(
+            rate_limiter.check_at("app", LIMIT, now),
+            Verdict::Drop { warn: false }
+        ));
+
+        // Once the bucket is full again, the next flood is a new one
+        let later = now + Duration::from_secs(10);
+
+        for _ in 0..3 {
+            assert!(allowed(rate_limiter.check_at("app", LIMIT, later)));
+        }
+
+        assert!(matches!(
+            rate_limiter.check_at("app", LIMIT, later),
+            Verdict::Drop { warn: true }
+        ));
+    }
+
+    #[test]
+    fn forgets_full_buckets() {
+        let mut rate_limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        rate_limiter.check_at("first", LIMIT, now);
+        rate_limiter.check_at("second", LIMIT, now);
+
+        assert_eq!(rate_limiter.buckets.len(), 2);
+
+        rate_limiter.check_at("second", LIMIT, now + Duration::from_secs(10));
+
+        assert_eq!(rate_limiter.buckets.len(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::notification::Notification;
+
+lazy_static! {
+    static ref RULES: RwLock<Vec<Rule>> = RwLock::new(Vec::new());
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    fn level(&self) -> u8 {
+        match self {
+            Urgency::Low => 0,
+            Urgency::Normal => 1,
+            Urgency::Critical => 2,
+        }
+    }
+}
+
+/// A `[[rule]]` in config.toml. Every rule that matches is applied, in order.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default, rename = "match")]
+    matches: Match,
+    // Actions
+    #[serde(default)]
+    suppress: bool,
+    urgency: Option<Urgency>,
+    timeout: Option<i32>,
+    theme: Option<String>,
+    screen: Option<String>,
+    silent: Option<bool>,
+    // Rewrites, where {app_name}, {summary} and {body} are replaced with the current values
+    app_name: Option<String>,
+    summary: Option<String>,
+    body: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Match {
+    app_name: Option<String>,
+    desktop_entry: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    summary: Option<Regex>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    body: Option<Regex>,
+    category: Option<String>,
+    urgency: Option<Urgency>,
+    sender: Option<String>,
+}
+
+impl Match {
+    fn matches(&self, notification: &Notification) -> bool {
+        let matches_text = |expected: &Option<String>, actual: &str| match expected {
+            Some(expected) => expected.eq_ignore_ascii_case(actual),
+            None => true,
+        };
+
+        let matches_regex = |regex: &Option<Regex>, text: &str| match regex {
+            Some(regex) => regex.is_match(text),
+            None => true,
+        };
+
+        // "email" matches both "email" and "email.arrived"
+        let matches_category = match &self.category {
+            Some(category) => {
+                notification.category == *category
+                    || notification
+                        .category
+                        .strip_prefix(category.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            }
+            None => true,
+        };
+
+        let matches_urgency = match self.urgency {
+            Some(urgency) => urgency.level() == notification.urgency,
+            None => true,
+        };
+
+        matches_text(&self.app_name, &notification.app_name)
+            && matches_text(&self.desktop_entry, &notification.desktop_entry)
+            && matches_text(&self.sender, &notification.sender)
+            && matches_regex(&self.summary, &notification.summary)
+            && matches_regex(&self.body, &notification.body)
+            && matches_category
+            && matches_urgency
+    }
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn rewrite(template: &str, notification: &Notification) -> String {
+    template
+        .replace("{app_name}", &notification.app_name)
+        .replace("{summary}", &notification.summary)
+        .replace("{body}", &notification.body)
+}
+
+/// `false` if a rule suppressed it.
+pub fn apply(notification: &mut Notification) -> bool {
+    apply_rules(
+        &RULES.read().expect("could not acquire rules lock"),
+        notification,
+    )
+}
+
+fn apply_rules(rules: &[Rule], notification: &mut Notification) -> bool {
+    for rule in rules {
+        if !rule.matches.matches(notification) {
+            continue;
+        }
+
+        if rule.suppress {
+            return false;
+        }
+
+        if let Some(urgency) = rule.urgency {
+            notification.urgency = urgency.level();
+        }
+
+        if let Some(timeout) = rule.timeout {
+            notification.timeout = Some(timeout);
+        }
+
+        if let Some(theme) = &rule.theme {
+            notification.theme = Some(theme.clone());
+        }
+
+        if let Some(screen) = &rule.screen {
+            notification.screen = Some(screen.clone());
+        }
+
+        if let Some(silent) = rule.silent {
+            notification.silent = silent;
+        }
+
+        // Every rewrite sees the values from before this rule was applied
+        let app_name = rule
+            .app_name
+            .as_ref()
+            .map(|app_name| rewrite(app_name, notification));
+        let summary = rule
+            .summary
+            .as_ref()
+            .map(|summary| rewrite(summary, notification));
+        let body = rule.body.as_ref().map(|body| rewrite(body, notification));
+
+        if let Some(app_name) = app_name {
+            notification.app_name = app_name;
+        }
+
+        if let Some(summary) = summary {
+            notification.summary = summary;
+        }
+
+        if let Some(body) = body {
+            notification.body = body;
+        }
+    }
+
+    true
+}
+
+/// Replaces the rules, every time the config is loaded.
+pub fn set_rules(rules: Vec<Rule>) {
+    *RULES.write().expect("could not acquire rules lock") = rules;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Rules {
+        rule: Vec<Rule>,
+    }
+
+    fn rules(config: &str) -> Vec<Rule> {
+        toml::from_str::<Rules>(config)
+            .expect("the test rules are valid")
+            .rule
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            app_name: String::from("Thunderbird"),
+            desktop_entry: String::from("org.mozilla.Thunderbird"),
+            summary: String::from("New mail from Alice"),
+            body: String::from("Lunch?"),
+            category: String::from("email.arrived"),
+            urgency: 1,
+            ..Notification::default()
+        }
+    }
+
+    fn matches(config: &str) -> bool {
+        rules(config)[0].matches.matches(&notification())
+    }
+
+    #[test]
+    fn empty_match_matches_everything() {
+        assert!(matches("[[rule]]"));
+    }
+
+    #[test]
+    fn names_ignore_case() {
+        assert!(matches("[[rule]]\nmatch.app_name = \"thunderbird\""));
+        assert!(matches(
+            "[[rule]]\nmatch.desktop_entry = \"ORG.MOZILLA.THUNDERBIRD\""
+        ));
+        assert!(!matches("[[rule]]\nmatch.app_name = \"thunder\""));
+    }
+
+    #[test]
+    fn desktop_entry_is_not_a_prefix() {
+        assert!(!matches("[[rule]]\nmatch.desktop_entry = \"org.mozilla\""));
+    }
+
+    #[test]
+    fn summary_and_body_are_regexes() {
+        assert!(matches("[[rule]]\nmatch.summary = \"^New mail\""));
+        assert!(matches("[[rule]]\nmatch.body = \"(?i)lunch\""));
+        assert!(!matches("[[rule]]\nmatch.summary = \"^mail\""));
+    }
+
+    #[test]
+    fn invalid_regexes_are_rejected() {
+        assert!(toml::from_str::<Rules>("[[rule]]\nmatch.summary = \"(\"").is_err());
+    }
+
+    #[test]
+    fn categories_match_their_subcategories() {
+        assert!(matches("[[rule]]\nmatch.category = \"email\""));
+        assert!(matches("[[rule]]\nmatch.category = \"email.arrived\""));
+        assert!(!matches("[[rule]]\nmatch.category = \"em\""));
+        assert!(!matches("[[rule]]\nmatch.category = \"email.bounced\""));
+    }
+
+    #[test]
+    fn every_field_has_to_match() {
+        assert!(matches(
+            "[[rule]]\nmatch = { app_name = \"thunderbird\", urgency = \"normal\" }"
+        ));
+        assert!(!matches(
+            "[[rule]]\nmatch = { app_name = \"thunderbird\", urgency = \"critical\" }"
+        ));
+    }
+
+    #[test]
+    fn suppressing_stops_the_notification() {
+        let mut notification = notification();
+
+        assert!(!apply_rules(
+            &rules("[[rule]]\nsuppress = true"),
+            &mut notification
+        ));
+        assert!(apply_rules(
+            &rules("[[rule]]\nmatch.app_name = \"slack\"\nsuppress = true"),
+            &mut notification
+        ));
+    }
+
+    #[test]
+    fn rewrites_see_the_values_from_before_the_rule() {
+        let mut notification = notification();
+
+        apply_rules(
+            &rules(
+                "[[rule]]\n\
+                 app_name = \"Mail\"\n\
+                 summary = \"{app_name}: {summary}\"\n\
+                 body = \"{summary}\"",
+            ),
+            &mut notification,
+        );
+
+        assert_eq!(notification.app_name, "Mail");
+        assert_eq!(notification.summary, "Thunderbird: New mail from Alice");
+        assert_eq!(notification.body, "New mail from Alice");
+    }
+
+    #[test]
+    fn later_rules_see_earlier_rewrites() {
+        let mut notification = notification();
+
+        apply_rules(
+            &rules(
+                "[[rule]]\n\
+                 app_name = \"Mail\"\n\
+                 urgency = \"critical\"\n\
+                 [[rule]]\n\
+                 match = { app_name = \"mail\", urgency = \"critical\" }\n\
+                 summary = \"[{app_name}] {summary}\"",
+            ),
+            &mut notification,
+        );
+
+        assert_eq!(notification.urgency, 2);
+        assert_eq!(notification.summary, "[Mail] New mail from Alice");
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+use zvariant::Value;
+
+use crate::file_watcher::FileWatcher;
+use crate::notification::Notification;
+use crate::settings::config_dir;
+
+lazy_static! {
+    static ref ENGINE: Engine = sandboxed_engine();
+    static ref SCRIPT: RwLock<Option<AST>> = RwLock::new(None);
+}
+
+/// An engine that can't touch anything outside of the notification it's given,
+/// and gives up on scripts that take too long.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(100_000)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(1024)
+        .set_max_map_size(1024)
+        .on_print(|text| println!("[script] {}", text))
+        .on_debug(|text, _, position| println!("[script] {:?}: {}", position, text));
+
+    engine.register_fn(
+        "add_action",
+        |notification: &mut Map, key: &str, label: &str| {
+            if let Some(actions) = notification.get_mut("actions") {
+                if let Some(mut actions) = actions.write_lock::<Array>() {
+                    actions.push(key.into());
+                    actions.push(label.into());
+                }
+            }
+        },
+    );
+
+    engine
+}
+
+fn urgency_name(urgency: u8) -> &'static str {
+    match urgency {
+        0 => "low",
+        2 => "critical",
+        _ => "normal",
+    }
+}
+
+fn urgency_level(name: &str) -> Option<u8> {
+    match name {
+        "low" => Some(0),
+        "normal" => Some(1),
+        "critical" => Some(2),
+        _ => None,
+    }
+}
+
+/// Only the hints that make sense as plain values are handed to scripts.
+fn hint_to_dynamic(value: &Value) -> Option<Dynamic> {
+    match value {
+        Value::Bool(value) => Some((*value).into()),
+        Value::U8(value) => Some((*value as i64).into()),
+        Value::I16(value) => Some((*value as i64).into()),
+        Value::U16(value) => Some((*value as i64).into()),
+        Value::I32(value) => Some((*value as i64).into()),
+        Value::U32(value) => Some((*value as i64).into()),
+        Value::I64(value) => Some((*value).into()),
+        Value::F64(value) => Some((*value).into()),
+        Value::Str(value) => Some(value.to_string().into()),
+        Value::ObjectPath(value) => Some(value.to_string().into()),
+        Value::Value(value) => hint_to_dynamic(value),
+        _ => None,
+    }
+}
+
+fn to_map(notification: &Notification, hints: &HashMap<String, Value>) -> Map {
+    let mut map = Map::new();
+
+    let hints: Map = hints
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str().into(), hint_to_dynamic(value)?)))
+        .collect();
+
+    let actions: Array = notification
+        .actions
+        .iter()
+        .map(|action| action.clone().into())
+        .collect();
+
+    map.insert("id".into(), (notification.notification_id as i64).into());
+    map.insert("app_name".into(), notification.app_name.clone().into());
+    map.insert("app_icon".into(), notification.app_icon.clone().into());
+    map.insert("summary".into(), notification.summary.clone().into());
+    map.insert("body".into(), notification.body.clone().into());
+    map.insert("actions".into(), actions.into());
+    map.insert("hints".into(), hints.into());
+    map.insert(
+        "desktop_entry".into(),
+        notification.desktop_entry.clone().into(),
+    );
+    map.insert("urgency".into(), urgency_name(notification.urgency).into());
+    map.insert("category".into(), notification.category.clone().into());
+    map.insert("sender".into(), notification.sender.clone().into());
+    map.insert(
+        "expire_timeout".into(),
+        (notification.timeout.unwrap_or(notification.expire_timeout) as i64).into(),
+    );
+    map.insert(
+        "theme".into(),
+        notification
+            .theme
+            .clone()
+            .map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    map.insert(
+        "screen".into(),
+        notification
+            .screen
+            .clone()
+            .map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    map.insert("silent".into(), notification.silent.into());
+
+    map
+}
+
+/// Copies back whatever the script changed. Values of the wrong type are reported and ignored.
+fn from_map(map: &Map, notification: &mut Notification) {
+    let string = |name: &str| -> Option<String> {
+        let value = map.get(name)?;
+
+        match value.clone().into_string() {
+            Ok(value) => Some(value),
+            Err(type_name) => {
+                eprintln!(
+                    "Script set `{}` to a {}, expected a string",
+                    name, type_name
+                );
+                None
+            }
+        }
+    };
+
+    // The theme and the screen can be unset again
+    let optional_string = |name: &str| -> Option<Option<String>> {
+        match map.get(name) {
+            Some(value) if value.is_unit() => Some(None),
+            Some(_) => string(name).map(Some),
+            None => None,
+        }
+    };
+
+    if let Some(app_name) = string("app_name") {
+        notification.app_name = app_name;
+    }
+
+    if let Some(app_icon) = string("app_icon") {
+        notification.app_icon = app_icon;
+    }
+
+    if let Some(summary) = string("summary") {
+        notification.summary = summary;
+    }
+
+    if let Some(body) = string("body") {
+        notification.body = body;
+    }
+
+    if let Some(category) = string("category") {
+        notification.category = category;
+    }
+
+    if let Some(urgency) = string("urgency") {
+        match urgency_level(&urgency) {
+            Some(urgency) => notification.urgency = urgency,
+            None => eprintln!("Script set an unknown urgency: {}", urgency),
+        }
+    }
+
+    if let Some(theme) = optional_string("theme") {
+        notification.theme = theme;
+    }
+
+    if let Some(screen) = optional_string("screen") {
+        notification.screen = screen;
+    }
+
+    if let Some(expire_timeout) = map.get("expire_timeout") {
+        match expire_timeout.as_int() {
+            // Only a timeout the script changed is used, like one set by a rule
+            Ok(expire_timeout)
+                if expire_timeout as i32
+                    != notification.timeout.unwrap_or(notification.expire_timeout) =>
+            {
+                notification.timeout = Some(expire_timeout as i32)
+            }
+            Ok(_) => (),
+            Err(type_name) => eprintln!(
+                "Script set `expire_timeout` to a {}, expected an integer",
+                type_name
+            ),
+        }
+    }
+
+    if let Some(silent) = map.get("silent") {
+        match silent.as_bool() {
+            Ok(silent) => notification.silent = silent,
+            Err(type_name) => {
+                eprintln!("Script set `silent` to a {}, expected a bool", type_name)
+            }
+        }
+    }
+
+    if let Some(actions) = map.get("actions") {
+        match actions.clone().into_typed_array::<String>() {
+            Ok(actions) => notification.actions = actions,
+            Err(type_name) => eprintln!(
+                "Script set `actions` to {}, expected an array of strings",
+                type_name
+            ),
+        }
+    }
+}
+
+/// `false` if the script dropped it. If the script fails, the notification is left as it was.
+pub fn apply(notification: &mut Notification, hints: &HashMap<String, Value>) -> bool {
+    let script = SCRIPT.read().expect("could not acquire script lock");
+
+    match script.as_ref() {
+        Some(ast) => run(ast, notification, hints),
+        None => true,
+    }
+}
+
+fn run(ast: &AST, notification: &mut Notification, hints: &HashMap<String, Value>) -> bool {
+    let mut scope = Scope::new();
+    scope.push("notification", to_map(notification, hints));
+
+    match ENGINE.eval_ast_with_scope::<Dynamic>(&mut scope, ast) {
+        // `return false` drops it
+        Ok(result) if result.as_bool() == Ok(false) => false,
+        Ok(_) => {
+            match scope.get_value::<Map>("notification") {
+                Some(map) => from_map(&map, notification),
+                None => eprintln!("Script replaced `notification` with something else"),
+            }
+
+            true
+        }
+        Err(err) => {
+            eprintln!("Script failed: {}", err);
+            true
+        }
+    }
+}
+
+pub fn script_path() -> PathBuf {
+    config_dir().join("script.rhai")
+}
+
+/// Compiles script.rhai again. If it doesn't compile, the previous one keeps running.
+pub fn load_script() {
+    let path = script_path();
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            SCRIPT
+                .write()
+                .expect("could not acquire script lock")
+                .take();
+            return;
+        }
+    };
+
+    match ENGINE.compile(contents) {
+        Ok(ast) => {
+            SCRIPT
+                .write()
+                .expect("could not acquire script lock")
+                .replace(ast);
+        }
+        Err(err) => eprintln!("Could not load {}: {}", path.display(), err),
+    }
+}
+
+/// Loads the script and reloads it every time the file changes.
+pub fn watch_script() -> Rc<FileWatcher> {
+    load_script();
+
+    FileWatcher::new(script_path(), load_script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            notification_id: 7,
+            app_name: String::from("Thunderbird"),
+            summary: String::from("New mail"),
+            body: String::from("Lunch?"),
+            actions: vec![String::from("default"), String::from("Open")],
+            urgency: 1,
+            expire_timeout: 5000,
+            theme: Some(String::from("dark")),
+            ..Notification::default()
+        }
+    }
+
+    fn run_script(script: &str, notification: &mut Notification) -> bool {
+        let hints = HashMap::from([(String::from("x-volume"), Value::from(40u32))]);
+        let ast = ENGINE.compile(script).expect("the test script compiles");
+
+        run(&ast, notification, &hints)
+    }
+
+    #[test]
+    fn round_trip_changes_nothing() {
+        let original = notification();
+        let mut notification = notification();
+
+        from_map(&to_map(&original, &HashMap::new()), &mut notification);
+
+        assert_eq!(notification.app_name, original.app_name);
+        assert_eq!(notification.summary, original.summary);
+        assert_eq!(notification.body, original.body);
+        assert_eq!(notification.actions, original.actions);
+        assert_eq!(notification.urgency, original.urgency);
+        assert_eq!(notification.theme, original.theme);
+        assert_eq!(notification.screen, original.screen);
+        assert_eq!(notification.silent, original.silent);
+        // The app's own timeout isn't turned into one that overrides the theme
+        assert_eq!(notification.timeout, None);
+    }
+
+    #[test]
+    fn returning_false_drops_it() {
+        let mut notification = notification();
+
+        assert!(!run_script("return false;", &mut notification));
+        assert!(!run_script("false", &mut notification));
+        assert!(run_script("true", &mut notification));
+        assert!(run_script("", &mut notification));
+    }
+
+    #[test]
+    fn changes_are_copied_back() {
+        let mut notification = notification();
+
+        assert!(run_script(
+            r#"
+                notification.summary = "[" + notification.app_name + "] " + notification.summary;
+                notification.urgency = "critical";
+                notification.expire_timeout = 0;
+                notification.theme = ();
+                notification.silent = notification.hints["x-volume"] > 30;
+                notification.add_action("archive", "Archive");
+            "#,
+            &mut notification
+        ));
+
+        assert_eq!(notification.summary, "[Thunderbird] New mail");
+        assert_eq!(notification.urgency, 2);
+        assert_eq!(notification.timeout, Some(0));
+        assert_eq!(notification.theme, None);
+        assert!(notification.silent);
+        assert_eq!(
+            notification.actions,
+            ["default", "Open", "archive", "Archive"]
+        );
+    }
+
+    #[test]
+    fn wrong_types_are_ignored() {
+        let mut notification = notification();
+
+        run_script(
+            r#"
+                notification.summary = 3;
+                notification.urgency = "urgent";
+                notification.silent = "yes";
+                notification.actions = [1, 2];
+            "#,
+            &mut notification,
+        );
+
+        assert_eq!(notification.summary, "New mail");
+        assert_eq!(notification.urgency, 1);
+        assert!(!notification.silent);
+        assert_eq!(notification.actions, ["default", "Open"]);
+    }
+
+    #[test]
+    fn failing_scripts_leave_it_alone() {
+        let mut notification = notification();
+
+        assert!(run_script(
+            r#"
+                notification.summary = "changed";
+                throw "oops";
+            "#,
+            &mut notification
+        ));
+        assert_eq!(notification.summary, "New mail");
+
+        assert!(run_script("loop {}", &mut notification));
+    }
+}
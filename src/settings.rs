@@ -1,29 +1,81 @@
+use std::env;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 use cpp_core::CppBox;
 use lazy_static::lazy_static;
 use qt_core::{qs, QBox, QPtr, QSettings, QVariant};
 use qt_gui::{QGuiApplication, QScreen};
+use serde::{Deserialize, Serialize};
+
+use crate::color_scheme;
+use crate::config::{self, save_value};
+use crate::rate_limiter::RateLimit;
+use crate::themes;
 
 lazy_static! {
     static ref THEME: Mutex<String> = Mutex::new("default".to_string());
     static ref SCREEN: Mutex<i32> = Mutex::new(-1);
     static ref DO_NOT_DISTURB: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    // What the D-Bus side reads, since SETTINGS is only touched on the Qt thread
+    static ref RATE_LIMITS: RwLock<Vec<(String, RateLimit)>> = RwLock::new(Vec::new());
 }
 
 static mut QSETTINGS: Option<QBox<QSettings>> = None;
 
 pub static mut SETTINGS: Settings = Settings {
-    theme: Theme { name: "" },
+    theme: Theme {
+        name: String::new(),
+    },
     screen: Screen {
         id: -1,
-        name: "",
+        name: String::new(),
         qscreen: None,
     },
+    position: Position::TopLeft,
     do_not_disturb: DoNotDisturb { value: false },
+    durations: Durations {
+        low: None,
+        normal: None,
+        critical: None,
+    },
+    max_notifications: MaxNotifications { value: 5 },
+    group_notifications: GroupNotifications { value: true },
+    duplicate_window: DuplicateWindow { value: 10000 },
+    quirks: Quirks { apps: Vec::new() },
+    rate_limits: RateLimits { apps: Vec::new() },
+    reduced_motion: ReducedMotion {
+        name: "auto",
+        value: false,
+    },
+    time_format: TimeFormat {
+        format: String::new(),
+    },
 };
 
+fn config_home() -> PathBuf {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".config"),
+    }
+}
+
+// Next to the QSettings file
+pub fn config_dir() -> PathBuf {
+    config_home().join(env!("CARGO_PKG_NAME"))
+}
+
+pub unsafe fn find_screen(name: &str) -> Option<i32> {
+    let screens = QGuiApplication::screens();
+
+    if let Ok(index) = name.parse::<i32>() {
+        return (0..screens.length()).contains(&index).then_some(index);
+    }
+
+    (0..screens.length()).find(|i| screens.value_1a(*i).name().to_std_string() == name)
+}
+
 pub trait Setting {
     fn load(&mut self);
     fn set(&mut self, value: CppBox<QVariant>);
@@ -33,7 +85,16 @@ pub trait Setting {
 pub struct Settings {
     pub theme: Theme,
     pub screen: Screen,
+    pub position: Position,
     pub do_not_disturb: DoNotDisturb,
+    pub durations: Durations,
+    pub max_notifications: MaxNotifications,
+    pub group_notifications: GroupNotifications,
+    pub duplicate_window: DuplicateWindow,
+    pub quirks: Quirks,
+    pub rate_limits: RateLimits,
+    pub reduced_motion: ReducedMotion,
+    pub time_format: TimeFormat,
 }
 
 pub unsafe fn load_settings() {
@@ -41,9 +102,11 @@ pub unsafe fn load_settings() {
         QSETTINGS = Some(QSettings::new());
     }
 
-    let mut theme = Theme { name: "default" };
+    let mut theme = Theme {
+        name: String::from(themes::DEFAULT_THEME),
+    };
     let mut screen = Screen {
-        name: "",
+        name: String::new(),
         id: -1,
         qscreen: None,
     };
@@ -51,19 +114,69 @@ pub unsafe fn load_settings() {
     theme.load();
     screen.load();
 
+    let mut position = Position::TopLeft;
+
+    position.load();
+
     let do_not_disturb = DoNotDisturb { value: false };
 
+    let mut max_notifications = MaxNotifications { value: 5 };
+
+    max_notifications.load();
+
+    let mut group_notifications = GroupNotifications { value: true };
+
+    group_notifications.load();
+
+    let mut duplicate_window = DuplicateWindow { value: 10000 };
+
+    duplicate_window.load();
+
+    let mut quirks = Quirks { apps: Vec::new() };
+
+    quirks.load();
+
+    let mut rate_limits = RateLimits { apps: Vec::new() };
+
+    rate_limits.load();
+
+    let mut reduced_motion = ReducedMotion {
+        name: "auto",
+        value: false,
+    };
+
+    reduced_motion.load();
+
+    let mut time_format = TimeFormat {
+        format: String::from("relative"),
+    };
+
+    time_format.load();
+
     let this = Settings {
         theme,
         screen,
+        position,
         do_not_disturb,
+        durations: Durations::default(),
+        max_notifications,
+        group_notifications,
+        duplicate_window,
+        quirks,
+        rate_limits,
+        reduced_motion,
+        time_format,
     };
 
     SETTINGS = this;
+
+    // Whatever was in QSettings is only used to write config.toml the first time
+    config::migrate();
+    config::load_config();
 }
 
 pub struct Theme {
-    pub name: &'static str,
+    pub name: String,
 }
 
 impl Setting for Theme {
@@ -77,31 +190,30 @@ impl Setting for Theme {
 
     fn set(&mut self, value: CppBox<QVariant>) {
         unsafe {
-            if value.is_null() {
-                self.name = "default";
+            let name = value.to_string().to_std_string();
+
+            if value.is_null() || name.is_empty() {
+                self.name = themes::DEFAULT_THEME.to_string();
+            } else if name != themes::DEFAULT_THEME && !themes::check_theme(&name) {
+                self.name = themes::DEFAULT_THEME.to_string();
             } else {
-                self.name = Box::leak(value.to_string().to_std_string().into_boxed_str());
+                self.name = name;
             }
 
             let mut _theme = THEME.lock().expect("Could not lock mutex");
 
-            *_theme = self.name.to_string();
+            *_theme = self.name.clone();
         }
     }
 
     fn save(&mut self) {
-        unsafe {
-            QSETTINGS.as_ref().unwrap().set_value(
-                &qs("theme"),
-                &QVariant::from_q_string(&qs(self.name.clone())),
-            );
-        }
+        save_value("theme", self.name.to_string());
     }
 }
 
 pub struct Screen {
     pub id: i32,
-    pub name: &'static str,
+    pub name: String,
     pub qscreen: Option<QPtr<QScreen>>,
 }
 
@@ -133,7 +245,7 @@ impl Setting for Screen {
                 }
             }
 
-            self.name = Box::leak(screen_name.into_boxed_str());
+            self.name = screen_name;
             self.id = screen_id;
             self.qscreen = qscreen;
 
@@ -144,15 +256,100 @@ impl Setting for Screen {
     }
 
     fn save(&mut self) {
+        save_value("screen", self.name.to_string());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Position {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Position::TopLeft => "top-left",
+            Position::TopRight => "top-right",
+            Position::BottomLeft => "bottom-left",
+            Position::BottomRight => "bottom-right",
+        }
+    }
+
+    pub fn is_bottom(&self) -> bool {
+        matches!(self, Position::BottomLeft | Position::BottomRight)
+    }
+
+    pub fn is_right(&self) -> bool {
+        matches!(self, Position::TopRight | Position::BottomRight)
+    }
+}
+
+impl Setting for Position {
+    fn load(&mut self) {
         unsafe {
-            QSETTINGS.as_ref().unwrap().set_value(
-                &qs("screen"),
-                &QVariant::from_q_string(&qs(self.name.clone())),
-            );
+            let position_setting = QSETTINGS
+                .as_ref()
+                .unwrap()
+                .value_2a(&qs("position"), &QVariant::from_q_string(&qs("top-left")));
+
+            self.set(position_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            *self = match value.to_string().to_std_string().as_str() {
+                "top-left" => Position::TopLeft,
+                "top-right" => Position::TopRight,
+                "bottom-left" => Position::BottomLeft,
+                "bottom-right" => Position::BottomRight,
+                other => {
+                    eprintln!("Unknown position \"{other}\", expected one of top-left, top-right, bottom-left or bottom-right");
+                    Position::TopLeft
+                }
+            };
+        }
+    }
+
+    fn save(&mut self) {
+        save_value("position", self.name().to_string());
+    }
+}
+
+// In ms. Unset ones use the theme's notificationDuration, and 0 means until dismissed.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Durations {
+    pub low: Option<i32>,
+    pub normal: Option<i32>,
+    pub critical: Option<i32>,
+}
+
+impl Durations {
+    // A rule's timeout wins over the urgency's
+    pub fn expire_timeout(&self, timeout: Option<i32>, urgency: u8) -> i32 {
+        if let Some(timeout) = timeout {
+            return timeout;
         }
+
+        let duration = match urgency {
+            0 => self.low,
+            2 => self.critical,
+            _ => self.normal,
+        };
+
+        duration.unwrap_or(-1)
     }
 }
 
+// Readable from any thread
+pub fn do_not_disturb() -> bool {
+    DO_NOT_DISTURB.load(Ordering::Relaxed)
+}
+
 pub struct DoNotDisturb {
     pub value: bool,
 }
@@ -170,6 +367,469 @@ impl Setting for DoNotDisturb {
     }
 
     fn save(&mut self) {
-        // Should I actually save the Do Not Disturb?
+        save_value("do_not_disturb", self.value);
+    }
+}
+
+pub struct MaxNotifications {
+    pub value: i32,
+}
+
+impl Setting for MaxNotifications {
+    fn load(&mut self) {
+        unsafe {
+            let max_notifications_setting = QSETTINGS
+                .as_ref()
+                .unwrap()
+                .value_2a(&qs("max_notifications"), &QVariant::from_int(5));
+
+            self.set(max_notifications_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            // 0 or less means there's no limit other than the screen height
+            self.value = value.to_int_0a();
+        }
+    }
+
+    fn save(&mut self) {
+        save_value("max_notifications", self.value as i64);
+    }
+}
+
+pub struct GroupNotifications {
+    pub value: bool,
+}
+
+impl Setting for GroupNotifications {
+    fn load(&mut self) {
+        unsafe {
+            let group_notifications_setting = QSETTINGS
+                .as_ref()
+                .unwrap()
+                .value_2a(&qs("group_notifications"), &QVariant::from_bool(true));
+
+            self.set(group_notifications_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            self.value = value.to_bool();
+        }
+    }
+
+    fn save(&mut self) {
+        save_value("group_notifications", self.value);
+    }
+}
+
+// How long (in ms) an exact duplicate is folded into the notification that's already up
+pub struct DuplicateWindow {
+    pub value: i32,
+}
+
+impl Setting for DuplicateWindow {
+    fn load(&mut self) {
+        unsafe {
+            let duplicate_window_setting = QSETTINGS
+                .as_ref()
+                .unwrap()
+                .value_2a(&qs("duplicate_window"), &QVariant::from_int(10000));
+
+            self.set(duplicate_window_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            self.value = value.to_int_0a();
+        }
+    }
+
+    fn save(&mut self) {
+        save_value("duplicate_window", self.value as i64);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quirk {
+    // The app sends `replaces_id` as one more than the ID it wants to replace
+    OffByOneId,
+    MatchingSummary,
+    ReplacePrevious,
+}
+
+impl Quirk {
+    fn from_name(name: &str) -> Option<Quirk> {
+        match name {
+            "off_by_one_id" => Some(Quirk::OffByOneId),
+            "matching_summary" => Some(Quirk::MatchingSummary),
+            "replace_previous" => Some(Quirk::ReplacePrevious),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Quirk::OffByOneId => "off_by_one_id",
+            Quirk::MatchingSummary => "matching_summary",
+            Quirk::ReplacePrevious => "replace_previous",
+        }
+    }
+
+    // 0 means it didn't ask to replace anything
+    pub fn intended_id(replaces_id: u32) -> Option<u32> {
+        replaces_id.checked_sub(1).filter(|id| *id != 0)
+    }
+}
+
+// Workarounds for apps that don't follow the spec when replacing, as `app:quirk` entries
+pub struct Quirks {
+    pub apps: Vec<(String, Quirk)>,
+}
+
+impl Quirks {
+    pub fn for_app(&self, app_name: &str, desktop_entry: &str) -> Vec<Quirk> {
+        self.apps
+            .iter()
+            .filter(|(app, _)| {
+                app.eq_ignore_ascii_case(app_name) || app.eq_ignore_ascii_case(desktop_entry)
+            })
+            .map(|(_, quirk)| *quirk)
+            .collect()
+    }
+}
+
+impl Quirks {
+    pub fn entries(&self) -> Vec<String> {
+        self.apps
+            .iter()
+            .map(|(app, quirk)| format!("{}:{}", app, quirk.name()))
+            .collect()
+    }
+}
+
+impl Setting for Quirks {
+    fn load(&mut self) {
+        unsafe {
+            let quirks_setting = QSETTINGS.as_ref().unwrap().value_2a(
+                &qs("quirks"),
+                &QVariant::from_q_string(&qs("discord:off_by_one_id")),
+            );
+
+            self.set(quirks_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            let entries = value.to_string_list();
+
+            self.apps.clear();
+
+            for i in 0..entries.length() {
+                let entry = entries.at(i).to_std_string();
+
+                match entry.split_once(':') {
+                    Some((app, quirk)) => match Quirk::from_name(quirk.trim()) {
+                        Some(quirk) => self.apps.push((app.trim().to_string(), quirk)),
+                        None => eprintln!("Unknown quirk \"{}\" for {}", quirk.trim(), app.trim()),
+                    },
+                    None => eprintln!("Quirks must look like app:quirk, got \"{entry}\""),
+                }
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        save_value(
+            "quirks",
+            self.entries().into_iter().collect::<toml_edit::Array>(),
+        );
+    }
+}
+
+// `app:burst/per_second` entries, where `*` is for every app that doesn't have its own
+pub struct RateLimits {
+    pub apps: Vec<(String, RateLimit)>,
+}
+
+// Readable from any thread
+pub fn rate_limit_for(app_name: &str, desktop_entry: &str) -> Option<RateLimit> {
+    let apps = RATE_LIMITS
+        .read()
+        .expect("could not acquire rate limits lock");
+
+    let app_rate_limit = apps.iter().find(|(app, _)| {
+        app.eq_ignore_ascii_case(app_name) || app.eq_ignore_ascii_case(desktop_entry)
+    });
+
+    app_rate_limit
+        .or_else(|| apps.iter().find(|(app, _)| app == "*"))
+        .map(|(_, rate_limit)| *rate_limit)
+}
+
+impl RateLimits {
+    fn parse(entry: &str) -> Option<(String, RateLimit)> {
+        let (app, rate_limit) = entry.rsplit_once(':')?;
+        let (burst, per_second) = rate_limit.split_once('/')?;
+        let burst: f64 = burst.trim().parse().ok()?;
+        let per_second: f64 = per_second.trim().parse().ok()?;
+
+        // Anything less than one in a row would drop every notification from the app
+        if !(burst >= 1.0 && burst.is_finite() && per_second >= 0.0 && per_second.is_finite()) {
+            return None;
+        }
+
+        Some((app.trim().to_string(), RateLimit { burst, per_second }))
+    }
+}
+
+impl RateLimits {
+    pub fn entries(&self) -> Vec<String> {
+        self.apps
+            .iter()
+            .map(|(app, rate_limit)| {
+                format!("{}:{}/{}", app, rate_limit.burst, rate_limit.per_second)
+            })
+            .collect()
+    }
+}
+
+impl Setting for RateLimits {
+    fn load(&mut self) {
+        unsafe {
+            let rate_limits_setting = QSETTINGS
+                .as_ref()
+                .unwrap()
+                .value_2a(&qs("rate_limits"), &QVariant::from_q_string(&qs("*:10/1")));
+
+            self.set(rate_limits_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            let entries = value.to_string_list();
+
+            self.apps.clear();
+
+            for i in 0..entries.length() {
+                let entry = entries.at(i).to_std_string();
+
+                match RateLimits::parse(&entry) {
+                    Some(rate_limit) => self.apps.push(rate_limit),
+                    None => eprintln!(
+                        "Rate limits must look like app:burst/per_second, got \"{entry}\""
+                    ),
+                }
+            }
+
+            *RATE_LIMITS
+                .write()
+                .expect("could not acquire rate limits lock") = self.apps.clone();
+        }
+    }
+
+    fn save(&mut self) {
+        save_value(
+            "rate_limits",
+            self.entries().into_iter().collect::<toml_edit::Array>(),
+        );
+    }
+}
+
+// Leaves out blur, shadows and animations, which are slow without a GPU
+pub struct ReducedMotion {
+    pub name: &'static str,
+    pub value: bool,
+}
+
+impl ReducedMotion {
+    // For auto, after the desktop's animation settings changed
+    pub fn refresh(&mut self) {
+        if self.name == "auto" {
+            self.value = color_scheme::desktop_prefers_reduced_motion();
+        }
+    }
+}
+
+impl Setting for ReducedMotion {
+    fn load(&mut self) {
+        unsafe {
+            let reduced_motion_setting = QSETTINGS
+                .as_ref()
+                .unwrap()
+                .value_2a(&qs("reduced_motion"), &QVariant::from_q_string(&qs("auto")));
+
+            self.set(reduced_motion_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            let (name, value) = match value.to_string().to_std_string().as_str() {
+                "on" => ("on", true),
+                "off" => ("off", false),
+                "auto" => ("auto", color_scheme::desktop_prefers_reduced_motion()),
+                other => {
+                    eprintln!(
+                        "Unknown reduced_motion \"{other}\", expected one of auto, on or off"
+                    );
+                    ("auto", color_scheme::desktop_prefers_reduced_motion())
+                }
+            };
+
+            self.name = name;
+            self.value = value;
+        }
+    }
+
+    fn save(&mut self) {
+        save_value("reduced_motion", self.name.to_string());
+    }
+}
+
+// "relative" ("now", "2 min ago", then the time of day), or a Qt date format like "HH:mm"
+pub struct TimeFormat {
+    pub format: String,
+}
+
+impl Setting for TimeFormat {
+    fn load(&mut self) {
+        unsafe {
+            let time_format_setting = QSETTINGS.as_ref().unwrap().value_2a(
+                &qs("time_format"),
+                &QVariant::from_q_string(&qs("relative")),
+            );
+
+            self.set(time_format_setting);
+        }
+    }
+
+    fn set(&mut self, value: CppBox<QVariant>) {
+        unsafe {
+            let format = value.to_string().to_std_string();
+
+            self.format = if format.is_empty() {
+                String::from("relative")
+            } else {
+                format
+            };
+        }
+    }
+
+    fn save(&mut self) {
+        save_value("time_format", self.format.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_follow_urgency() {
+        let durations = Durations {
+            low: Some(3000),
+            normal: None,
+            critical: Some(0),
+        };
+
+        assert_eq!(durations.expire_timeout(None, 0), 3000);
+        assert_eq!(durations.expire_timeout(None, 1), -1);
+        assert_eq!(durations.expire_timeout(None, 2), 0);
+        // Out of range urgencies are treated as normal, like everywhere else
+        assert_eq!(durations.expire_timeout(None, 7), -1);
+    }
+
+    #[test]
+    fn rule_timeouts_win() {
+        let durations = Durations {
+            low: Some(3000),
+            normal: Some(5000),
+            critical: Some(0),
+        };
+
+        assert_eq!(durations.expire_timeout(Some(1000), 2), 1000);
+        assert_eq!(durations.expire_timeout(Some(0), 0), 0);
+    }
+
+    #[test]
+    fn quirk_names_round_trip() {
+        for quirk in [
+            Quirk::OffByOneId,
+            Quirk::MatchingSummary,
+            Quirk::ReplacePrevious,
+        ] {
+            assert_eq!(Quirk::from_name(quirk.name()), Some(quirk));
+        }
+
+        assert_eq!(Quirk::from_name("Off_By_One_Id"), None);
+        assert_eq!(Quirk::from_name(""), None);
+    }
+
+    #[test]
+    fn off_by_one_ids() {
+        assert_eq!(Quirk::intended_id(5), Some(4));
+        assert_eq!(Quirk::intended_id(1), None);
+        assert_eq!(Quirk::intended_id(0), None);
+    }
+
+    #[test]
+    fn parses_rate_limits() {
+        assert_eq!(
+            RateLimits::parse("build-bot:3/0.1"),
+            Some((
+                String::from("build-bot"),
+                RateLimit {
+                    burst: 3.0,
+                    per_second: 0.1,
+                }
+            ))
+        );
+        assert_eq!(
+            RateLimits::parse(" * : 10 / 1 "),
+            Some((
+                String::from("*"),
+                RateLimit {
+                    burst: 10.0,
+                    per_second: 1.0,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn app_names_can_have_colons() {
+        assert_eq!(
+            RateLimits::parse("org.app:beta:5/1").map(|(app, _)| app),
+            Some(String::from("org.app:beta"))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_rate_limits() {
+        for entry in [
+            "",
+            "app",
+            "app:10",
+            "app:10/",
+            "app:/1",
+            "app:ten/1",
+            "app:10/one",
+            "app:0/1",
+            "app:0.5/1",
+            "app:-3/1",
+            "app:3/-1",
+            "app:inf/1",
+            "app:NaN/1",
+            "app:3/NaN",
+        ] {
+            assert_eq!(RateLimits::parse(entry), None, "{entry:?} was accepted");
+        }
     }
 }
@@ -0,0 +1,638 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cpp_core::{CppBox, CppDeletable};
+use qt_core::{
+    q_easing_curve, q_io_device::OpenModeFlag, qs, QBuffer, QByteArray, QFile, QFlags, QPtr,
+    QVariant,
+};
+use qt_widgets::QWidget;
+
+use crate::color_scheme::{self, ColorScheme};
+use crate::errors::KrustifyError;
+use crate::notification::Notification;
+use crate::theme_assets;
+use crate::themes::{self, Manifest};
+
+pub const TEMPLATE_FILE: &str = "template.ui";
+
+const URGENCY_FILES: [&str; 3] = ["low.ui", "normal.ui", "critical.ui"];
+
+/// Used for notifications that update in place, like volume changes
+const OSD_FILE: &str = "osd.ui";
+
+const EASING_CURVES: [(&str, q_easing_curve::Type); 45] = [
+    ("Linear", q_easing_curve::Type::Linear),
+    ("InQuad", q_easing_curve::Type::InQuad),
+    ("OutQuad", q_easing_curve::Type::OutQuad),
+    ("InOutQuad", q_easing_curve::Type::InOutQuad),
+    ("OutInQuad", q_easing_curve::Type::OutInQuad),
+    ("InCubic", q_easing_curve::Type::InCubic),
+    ("OutCubic", q_easing_curve::Type::OutCubic),
+    ("InOutCubic", q_easing_curve::Type::InOutCubic),
+    ("OutInCubic", q_easing_curve::Type::OutInCubic),
+    ("InQuart", q_easing_curve::Type::InQuart),
+    ("OutQuart", q_easing_curve::Type::OutQuart),
+    ("InOutQuart", q_easing_curve::Type::InOutQuart),
+    ("OutInQuart", q_easing_curve::Type::OutInQuart),
+    ("InQuint", q_easing_curve::Type::InQuint),
+    ("OutQuint", q_easing_curve::Type::OutQuint),
+    ("InOutQuint", q_easing_curve::Type::InOutQuint),
+    ("OutInQuint", q_easing_curve::Type::OutInQuint),
+    ("InSine", q_easing_curve::Type::InSine),
+    ("OutSine", q_easing_curve::Type::OutSine),
+    ("InOutSine", q_easing_curve::Type::InOutSine),
+    ("OutInSine", q_easing_curve::Type::OutInSine),
+    ("InExpo", q_easing_curve::Type::InExpo),
+    ("OutExpo", q_easing_curve::Type::OutExpo),
+    ("InOutExpo", q_easing_curve::Type::InOutExpo),
+    ("OutInExpo", q_easing_curve::Type::OutInExpo),
+    ("InCirc", q_easing_curve::Type::InCirc),
+    ("OutCirc", q_easing_curve::Type::OutCirc),
+    ("InOutCirc", q_easing_curve::Type::InOutCirc),
+    ("OutInCirc", q_easing_curve::Type::OutInCirc),
+    ("InElastic", q_easing_curve::Type::InElastic),
+    ("OutElastic", q_easing_curve::Type::OutElastic),
+    ("InOutElastic", q_easing_curve::Type::InOutElastic),
+    ("OutInElastic", q_easing_curve::Type::OutInElastic),
+    ("InBack", q_easing_curve::Type::InBack),
+    ("OutBack", q_easing_curve::Type::OutBack),
+    ("InOutBack", q_easing_curve::Type::InOutBack),
+    ("OutInBack", q_easing_curve::Type::OutInBack),
+    ("InBounce", q_easing_curve::Type::InBounce),
+    ("OutBounce", q_easing_curve::Type::OutBounce),
+    ("InOutBounce", q_easing_curve::Type::InOutBounce),
+    ("OutInBounce", q_easing_curve::Type::OutInBounce),
+    ("InCurve", q_easing_curve::Type::InCurve),
+    ("OutCurve", q_easing_curve::Type::OutCurve),
+    ("SineCurve", q_easing_curve::Type::SineCurve),
+    ("CosineCurve", q_easing_curve::Type::CosineCurve),
+];
+
+type Cache = HashMap<(String, Vec<String>, ColorScheme), Rc<Template>>;
+
+// Only touched from the Qt thread, since the templates hold widgets
+static mut TEMPLATES: Option<Cache> = None;
+
+/// The templates a notification could use, most specific first.
+pub fn variants(notification: &Notification) -> Vec<String> {
+    let mut variants = Vec::new();
+
+    if notification.synchronous.is_some() {
+        variants.push(String::from(OSD_FILE));
+    }
+
+    // It comes from the app, so it can't be trusted to be a plain file name
+    let category = &notification.category;
+
+    if !category.is_empty()
+        && !category.starts_with('.')
+        && category
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    {
+        variants.push(format!("category/{category}.ui"));
+
+        if let Some((class, _)) = category.split_once('.') {
+            variants.push(format!("category/{class}.ui"));
+        }
+    }
+
+    variants.push(String::from(
+        URGENCY_FILES[(notification.urgency as usize).min(URGENCY_FILES.len() - 1)],
+    ));
+    variants.push(String::from(TEMPLATE_FILE));
+
+    variants
+}
+
+pub fn easing_curve(name: &str) -> Option<q_easing_curve::Type> {
+    EASING_CURVES
+        .iter()
+        .find(|(curve, _)| *curve == name)
+        .map(|(_, curve_type)| *curve_type)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationStyle {
+    SlideTop,
+    SlideLeft,
+    SlideRight,
+    SlideBottom,
+    Fade,
+    Scale,
+    None,
+}
+
+impl AnimationStyle {
+    pub fn from_name(name: &str) -> Option<AnimationStyle> {
+        match name {
+            "slideTop" => Some(AnimationStyle::SlideTop),
+            "slideLeft" => Some(AnimationStyle::SlideLeft),
+            "slideRight" => Some(AnimationStyle::SlideRight),
+            "slideBottom" => Some(AnimationStyle::SlideBottom),
+            "fade" => Some(AnimationStyle::Fade),
+            "scale" => Some(AnimationStyle::Scale),
+            "none" => Some(AnimationStyle::None),
+            _ => None,
+        }
+    }
+
+    /// Whether it moves or resizes the widget, rather than fading it.
+    pub fn changes_geometry(self) -> bool {
+        !matches!(self, AnimationStyle::Fade | AnimationStyle::None)
+    }
+}
+
+/// Every template anywhere in the theme's chain, template.ui first.
+pub fn variant_files(chain: &[(PathBuf, Manifest)]) -> Vec<String> {
+    let mut files = BTreeSet::new();
+
+    for (theme, _) in chain {
+        for file in URGENCY_FILES.iter().chain([&OSD_FILE]) {
+            if theme.join(file).is_file() {
+                files.insert(file.to_string());
+            }
+        }
+
+        if let Ok(entries) = fs::read_dir(theme.join("category")) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+
+                if name.ends_with(".ui") {
+                    files.insert(format!("category/{name}"));
+                }
+            }
+        }
+    }
+
+    let mut variant_files = vec![String::from(TEMPLATE_FILE)];
+    variant_files.extend(files);
+
+    variant_files
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Properties {
+    pub default_opacity: f64,
+    pub hovered_opacity: f64,
+    // 1.0 shifts the widget slightly to the bottom-right for some reason
+    pub focused_opacity: f64,
+    pub default_blur: f64,
+    pub hovered_blur: f64,
+    pub end_blur: f64,
+    pub notification_duration: i32,
+    pub spawn_duration: i32,
+    pub disappear_duration: i32,
+    pub hover_duration: i32,
+    pub move_duration: i32,
+    pub entry_animation: String,
+    pub exit_animation: String,
+    pub entry_easing: String,
+    pub exit_easing: String,
+    pub move_easing: String,
+    pub default_shadow_color: String,
+    pub focused_shadow_color: String,
+    pub default_shadow_blur: f64,
+    pub focused_shadow_blur: f64,
+    pub shadow_offset: f64,
+    pub text_shadow_color: String,
+    pub text_shadow_blur: f64,
+    pub icon_size: i32,
+    pub icon_fit: String,
+    pub icon_clip: String,
+    pub icon_roundness: i32,
+    pub image_fit: String,
+    pub image_clip: String,
+    // From 0 (square) to 99
+    pub image_roundness: i32,
+}
+
+impl Properties {
+    unsafe fn read(template: &QPtr<QWidget>) -> Properties {
+        let property = |name: &str| {
+            template.property(
+                CString::new(name)
+                    .expect("property names have no nul bytes")
+                    .as_ptr(),
+            )
+        };
+
+        // The ones themes don't have to set
+        let double = |name: &str, default: f64| {
+            let value = property(name);
+
+            if value.is_valid() {
+                value.to_double_0a()
+            } else {
+                default
+            }
+        };
+        let int = |name: &str, default: i32| {
+            let value = property(name);
+
+            if value.is_valid() {
+                value.to_int_0a()
+            } else {
+                default
+            }
+        };
+        let string = |name: &str, default: &str| {
+            let value = property(name);
+
+            if value.is_valid() {
+                value.to_string().to_std_string()
+            } else {
+                default.to_string()
+            }
+        };
+
+        let spawn_duration = property("spawnDuration").to_int_0a();
+
+        Properties {
+            default_opacity: property("defaultOpacity").to_double_0a(),
+            hovered_opacity: property("hoveredOpacity").to_double_0a(),
+            focused_opacity: double("focusedOpacity", 0.99),
+            default_blur: property("defaultBlur").to_double_0a(),
+            hovered_blur: property("hoveredBlur").to_double_0a(),
+            end_blur: property("endBlur").to_double_0a(),
+            notification_duration: property("notificationDuration").to_int_0a(),
+            spawn_duration,
+            disappear_duration: property("disappearDuration").to_int_0a(),
+            hover_duration: int("hoverDuration", 100),
+            move_duration: int("moveDuration", spawn_duration),
+            entry_animation: string("entryAnimation", "slideTop"),
+            exit_animation: string("exitAnimation", "fade"),
+            entry_easing: string("entryEasing", "Linear"),
+            exit_easing: string("exitEasing", "OutCurve"),
+            move_easing: string("moveEasing", "OutCubic"),
+            default_shadow_color: property("defaultShadowColor").to_string().to_std_string(),
+            focused_shadow_color: property("focusedShadowColor").to_string().to_std_string(),
+            default_shadow_blur: double("defaultShadowBlur", 10.0),
+            focused_shadow_blur: double("focusedShadowBlur", 15.0),
+            shadow_offset: double("shadowOffset", 1.0),
+            text_shadow_color: property("textShadowColor").to_string().to_std_string(),
+            text_shadow_blur: double("textShadowBlur", 1.0),
+            icon_size: int("iconSize", 64),
+            icon_fit: string("iconFit", "contain"),
+            icon_clip: string("iconClip", "none"),
+            icon_roundness: int("iconRoundness", 25),
+            image_fit: string("imageFit", "cover"),
+            image_clip: string("imageClip", "rounded"),
+            image_roundness: int("imageRoundness", 25),
+        }
+    }
+}
+
+/// A theme's template, read from disk once, with what every theme up its chain overrides.
+#[derive(Debug)]
+pub struct Template {
+    ui: CppBox<QByteArray>,
+    /// Widget names and the .ui they're replaced with, parents first
+    widgets: Vec<(String, CppBox<QByteArray>)>,
+    overrides: Vec<(String, toml::Value)>,
+    stylesheet: String,
+    pub properties: Properties,
+}
+
+impl Template {
+    /// Uses the first of the `variants` found up the theme's chain.
+    pub unsafe fn load(
+        dir: &Path,
+        variants: &[String],
+        scheme: ColorScheme,
+    ) -> Result<Template, String> {
+        let chain = themes::theme_chain(dir)?;
+
+        let (base, file) = variants
+            .iter()
+            .find_map(|variant| {
+                chain
+                    .iter()
+                    .position(|(theme, _)| theme.join(variant).is_file())
+                    .map(|base| (base, variant))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "neither it nor the themes it extends have a {}",
+                    variants.join(" or ")
+                )
+            })?;
+
+        let mut template = Template {
+            ui: read_file(&chain[base].0.join(file))?,
+            widgets: Vec::new(),
+            overrides: Vec::new(),
+            stylesheet: String::new(),
+            properties: Properties::default(),
+        };
+
+        // A theme's own .ui replaces its parents', overrides are applied on top of it
+        for (theme, manifest) in chain[..=base].iter().rev() {
+            for (name, file) in &manifest.widgets {
+                template
+                    .widgets
+                    .push((name.clone(), read_file(&theme.join(file))?));
+            }
+
+            template.add_overrides(theme, &manifest.properties, &manifest.stylesheets)?;
+
+            let scheme_overrides = manifest.scheme(scheme);
+
+            template.add_overrides(
+                theme,
+                &scheme_overrides.properties,
+                &scheme_overrides.stylesheets,
+            )?;
+        }
+
+        let widget = template
+            .build()
+            .map_err(|_| format!("{file} couldn't be loaded"))?;
+
+        template.properties = Properties::read(&widget);
+
+        widget.close();
+        widget.delete();
+
+        Ok(template)
+    }
+
+    fn add_overrides(
+        &mut self,
+        theme: &Path,
+        properties: &BTreeMap<String, toml::Value>,
+        stylesheets: &[String],
+    ) -> Result<(), String> {
+        for (name, value) in properties {
+            if !matches!(
+                value,
+                toml::Value::String(_)
+                    | toml::Value::Integer(_)
+                    | toml::Value::Float(_)
+                    | toml::Value::Boolean(_)
+            ) {
+                return Err(format!(
+                    "the {name} property has to be a string, a number or a bool"
+                ));
+            }
+
+            self.overrides.push((name.clone(), value.clone()));
+        }
+
+        for file in stylesheets {
+            let path = theme.join(file);
+            let stylesheet = fs::read_to_string(&path)
+                .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+
+            self.stylesheet.push('\n');
+            self.stylesheet.push_str(&stylesheet);
+        }
+
+        Ok(())
+    }
+
+    /// The caller owns the new widgets and has to delete them.
+    pub unsafe fn build(&self) -> Result<QPtr<QWidget>, KrustifyError> {
+        let root = load_ui(&self.ui);
+
+        if root.is_null() {
+            return Err(KrustifyError::Other {
+                message: String::from("the template couldn't be loaded"),
+            });
+        }
+
+        for (name, ui) in &self.widgets {
+            replace_widget(&root, name, load_ui(ui));
+        }
+
+        for (name, value) in &self.overrides {
+            let value = match value {
+                toml::Value::String(value) => QVariant::from_q_string(&qs(value)),
+                toml::Value::Integer(value) => QVariant::from_int(*value as i32),
+                toml::Value::Float(value) => QVariant::from_double(*value),
+                toml::Value::Boolean(value) => QVariant::from_bool(*value),
+                _ => continue,
+            };
+
+            root.set_property(
+                CString::new(name.as_str()).unwrap_or_default().as_ptr(),
+                &value,
+            );
+        }
+
+        if !self.stylesheet.is_empty() {
+            root.set_style_sheet(&qs(root.style_sheet().to_std_string() + &self.stylesheet));
+        }
+
+        Ok(root)
+    }
+}
+
+unsafe fn read_file(path: &Path) -> Result<CppBox<QByteArray>, String> {
+    let file = QFile::from_q_string(&qs(path.to_string_lossy()));
+
+    if !file.open(QFlags::from(OpenModeFlag::ReadOnly)) {
+        return Err(format!("could not read {}", path.display()));
+    }
+
+    let contents = file.read_all();
+    file.close();
+
+    Ok(contents)
+}
+
+// Null if the .ui isn't valid
+unsafe fn load_ui(ui: &CppBox<QByteArray>) -> QPtr<QWidget> {
+    let buffer = QBuffer::new_0a();
+    buffer.set_data_q_byte_array(ui);
+    buffer.open(QFlags::from(OpenModeFlag::ReadOnly));
+    let loader = qt_ui_tools::QUiLoader::new_0a();
+    let widget = loader.load_1a(buffer.as_ptr());
+    buffer.close();
+    loader.delete();
+
+    widget
+}
+
+/// Puts `replacement` where the widget called `name` was, taking its name.
+unsafe fn replace_widget(root: &QPtr<QWidget>, name: &str, replacement: QPtr<QWidget>) {
+    if replacement.is_null() {
+        eprintln!("The replacement for \"{name}\" couldn't be loaded");
+        return;
+    }
+
+    let widget: QPtr<QWidget> = match root.find_child(name) {
+        Ok(widget) => widget,
+        Err(_) => {
+            eprintln!("There's no \"{name}\" widget to replace");
+            replacement.delete();
+            return;
+        }
+    };
+
+    let parent = widget.parent_widget();
+
+    replacement.set_object_name(&qs(name));
+    replacement.set_parent_1a(&parent);
+
+    let layout = parent.layout();
+
+    if layout.is_null() {
+        replacement.set_geometry_1a(&widget.geometry());
+    } else {
+        // The layout item it hands back is ours to delete
+        drop(layout.replace_widget_2a(&widget, &replacement).to_box());
+    }
+
+    widget.delete();
+}
+
+/// Falls back to the default theme if the given one can't be found or isn't valid.
+pub unsafe fn load_template(
+    theme: &str,
+    variants: &[String],
+) -> Result<Rc<Template>, KrustifyError> {
+    let scheme = color_scheme::current();
+    let key = (theme.to_string(), variants.to_vec(), scheme);
+
+    if let Some(template) = TEMPLATES.as_ref().and_then(|templates| templates.get(&key)) {
+        return Ok(template.clone());
+    }
+
+    if theme != themes::DEFAULT_THEME && !themes::check_theme(theme) {
+        return load_template(themes::DEFAULT_THEME, variants);
+    }
+
+    // Its .ui files can use what's in them from here on
+    theme_assets::register(theme);
+
+    // Failures aren't cached, so a fixed theme is picked up by the next notification
+    let template = match themes::find_theme(theme)
+        .ok_or_else(|| format!("the \"{theme}\" theme isn't installed"))
+        .and_then(|dir| Template::load(&dir, variants, scheme))
+    {
+        Ok(template) => Rc::new(template),
+        Err(err) if theme != themes::DEFAULT_THEME => {
+            eprintln!("Could not load the \"{theme}\" theme: {err}");
+            return load_template(themes::DEFAULT_THEME, variants);
+        }
+        Err(err) => {
+            return Err(KrustifyError::Other {
+                message: format!("could not load the \"{theme}\" theme: {err}"),
+            })
+        }
+    };
+
+    TEMPLATES
+        .get_or_insert_with(HashMap::new)
+        .insert(key, template.clone());
+
+    Ok(template)
+}
+
+/// Makes the next `load_template` read the theme's files again, dropping its fonts until then.
+pub unsafe fn forget_template(theme: &str) {
+    theme_assets::unregister(theme);
+
+    if let Some(templates) = TEMPLATES.as_mut() {
+        templates.retain(|(cached_theme, _, _), _| cached_theme != theme);
+    }
+}
+
+/// Like `forget_template`, but its resources and fonts stay while notifications still use them.
+pub unsafe fn retire_template(theme: &str) {
+    if let Some(templates) = TEMPLATES.as_mut() {
+        templates.retain(|(cached_theme, _, _), _| cached_theme != theme);
+    }
+
+    theme_assets::retire(theme);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(category: &str, urgency: u8) -> Notification {
+        Notification {
+            category: String::from(category),
+            urgency,
+            ..Notification::default()
+        }
+    }
+
+    #[test]
+    fn variants_go_from_specific_to_general() {
+        assert_eq!(
+            variants(&notification("email.arrived", 2)),
+            [
+                "category/email.arrived.ui",
+                "category/email.ui",
+                "critical.ui",
+                "template.ui"
+            ]
+        );
+        assert_eq!(variants(&notification("", 0)), ["low.ui", "template.ui"]);
+    }
+
+    #[test]
+    fn synchronous_notifications_use_the_osd() {
+        let mut notification = notification("", 1);
+
+        notification.synchronous = Some(String::from("volume"));
+
+        assert_eq!(
+            variants(&notification),
+            ["osd.ui", "normal.ui", "template.ui"]
+        );
+    }
+
+    #[test]
+    fn unsafe_categories_are_ignored() {
+        for category in [
+            "../../etc/passwd",
+            "email/arrived",
+            ".hidden",
+            "..",
+            "email arrived",
+            "émail",
+        ] {
+            assert_eq!(
+                variants(&notification(category, 1)),
+                ["normal.ui", "template.ui"],
+                "{category:?} was used"
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_urgencies_are_critical() {
+        assert_eq!(
+            variants(&notification("", 200)),
+            ["critical.ui", "template.ui"]
+        );
+    }
+
+    #[test]
+    fn easing_curves_by_name() {
+        assert_eq!(easing_curve("Linear"), Some(q_easing_curve::Type::Linear));
+        assert_eq!(easing_curve("OutBack"), Some(q_easing_curve::Type::OutBack));
+        assert_eq!(
+            easing_curve("OutInBounce"),
+            Some(q_easing_curve::Type::OutInBounce)
+        );
+        assert_eq!(easing_curve("outBack"), None);
+        assert_eq!(easing_curve("Custom"), None);
+        assert_eq!(easing_curve(""), None);
+    }
+
+    #[test]
+    fn easing_curve_names_are_unique() {
+        let names: BTreeSet<&str> = EASING_CURVES.iter().map(|(name, _)| *name).collect();
+
+        assert_eq!(names.len(), EASING_CURVES.len());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use qt_core::{q_library_info::LibraryLocation, qs, QLibraryInfo, QResource};
+use qt_gui::QFontDatabase;
+
+use crate::themes;
+
+// Only said once, instead of every time a theme is loaded
+static MISSING_RCC_REPORTED: AtomicBool = AtomicBool::new(false);
+
+// Qt reads the resources from this memory until they're unregistered
+#[derive(Default)]
+struct Assets {
+    resources: Vec<Vec<u8>>,
+    fonts: Vec<i32>,
+}
+
+lazy_static! {
+    static ref REGISTERED: Mutex<HashMap<String, Assets>> = Mutex::new(HashMap::new());
+    // How many notifications on screen use each theme
+    static ref IN_USE: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+    // Unregistered once the last notification using them is gone
+    static ref RETIRED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// Lets templates use `:/` paths and the fonts in the fonts folder, up the theme's chain
+pub unsafe fn register(theme: &str) {
+    // It's wanted again, so it stays
+    RETIRED
+        .lock()
+        .expect("could not acquire retired themes lock")
+        .remove(theme);
+
+    if REGISTERED
+        .lock()
+        .expect("could not acquire theme assets lock")
+        .contains_key(theme)
+    {
+        return;
+    }
+
+    let dir = match themes::find_theme(theme) {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let dirs: Vec<PathBuf> = match themes::theme_chain(&dir) {
+        Ok(chain) => chain.into_iter().map(|(dir, _)| dir).collect(),
+        Err(_) => vec![dir],
+    };
+
+    let mut assets = Assets::default();
+
+    for dir in &dirs {
+        for resource in read_resources(dir) {
+            if QResource::register_resource_uchar(resource.as_ptr()) {
+                assets.resources.push(resource);
+            } else {
+                eprintln!("The resources of the \"{theme}\" theme couldn't be registered");
+            }
+        }
+
+        for font in files_with_extension(&dir.join("fonts"), &["ttf", "otf"]) {
+            let id = QFontDatabase::add_application_font(&qs(font.to_string_lossy()));
+
+            if id == -1 {
+                eprintln!("Could not load the font {}", font.display());
+            } else {
+                assets.fonts.push(id);
+            }
+        }
+    }
+
+    REGISTERED
+        .lock()
+        .expect("could not acquire theme assets lock")
+        .insert(theme.to_string(), assets);
+}
+
+pub unsafe fn unregister(theme: &str) {
+    RETIRED
+        .lock()
+        .expect("could not acquire retired themes lock")
+        .remove(theme);
+
+    let assets = match REGISTERED
+        .lock()
+        .expect("could not acquire theme assets lock")
+        .remove(theme)
+    {
+        Some(assets) => assets,
+        None => return,
+    };
+
+    for resource in &assets.resources {
+        QResource::unregister_resource_uchar(resource.as_ptr());
+    }
+
+    for id in assets.fonts {
+        QFontDatabase::remove_application_font(id);
+    }
+}
+
+pub fn acquire(theme: &str) {
+    *IN_USE
+        .lock()
+        .expect("could not acquire theme use lock")
+        .entry(theme.to_string())
+        .or_default() += 1;
+}
+
+pub unsafe fn release(theme: &str) {
+    let unused = {
+        let mut in_use = IN_USE.lock().expect("could not acquire theme use lock");
+
+        match in_use.get_mut(theme) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            _ => {
+                in_use.remove(theme);
+                true
+            }
+        }
+    };
+
+    let retired = RETIRED
+        .lock()
+        .expect("could not acquire retired themes lock")
+        .contains(theme);
+
+    if unused && retired {
+        unregister(theme);
+    }
+}
+
+// After another theme was picked, it's unregistered once no notification uses it anymore
+pub unsafe fn retire(theme: &str) {
+    let in_use = IN_USE
+        .lock()
+        .expect("could not acquire theme use lock")
+        .contains_key(theme);
+
+    if in_use {
+        RETIRED
+            .lock()
+            .expect("could not acquire retired themes lock")
+            .insert(theme.to_string());
+    } else {
+        unregister(theme);
+    }
+}
+
+// .qrc files are only compiled when there's no .rcc next to them
+unsafe fn read_resources(dir: &Path) -> Vec<Vec<u8>> {
+    let mut resources = Vec::new();
+
+    for file in files_with_extension(dir, &["rcc", "qrc"]) {
+        let is_qrc = file
+            .extension()
+            .map_or(false, |extension| extension.eq_ignore_ascii_case("qrc"));
+
+        if is_qrc && file.with_extension("rcc").is_file() {
+            continue;
+        }
+
+        let resource = if is_qrc {
+            compile(&file)
+        } else {
+            fs::read(&file).map(Some).map_err(|err| err.to_string())
+        };
+
+        match resource {
+            Ok(Some(resource)) => resources.push(resource),
+            Ok(None) => (),
+            Err(err) => eprintln!("Could not load {}: {}", file.display(), err),
+        }
+    }
+
+    resources
+}
+
+// None if there's no rcc where Qt is installed or in the PATH
+unsafe fn compile(qrc: &Path) -> Result<Option<Vec<u8>>, String> {
+    let installed_rcc =
+        PathBuf::from(QLibraryInfo::location(LibraryLocation::BinariesPath).to_std_string())
+            .join("rcc");
+
+    let candidates = [
+        installed_rcc,
+        PathBuf::from("rcc"),
+        PathBuf::from("rcc-qt5"),
+    ];
+
+    for rcc in candidates {
+        // The files it lists are relative to it
+        let output = match Command::new(&rcc)
+            .arg("--binary")
+            .arg(qrc.file_name().unwrap_or_default())
+            .current_dir(qrc.parent().unwrap_or_else(|| Path::new(".")))
+            .output()
+        {
+            Ok(output) => output,
+            Err(_) => continue,
+        };
+
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+
+        return Ok(Some(output.stdout));
+    }
+
+    if !MISSING_RCC_REPORTED.swap(true, Ordering::Relaxed) {
+        eprintln!("rcc isn't installed, so themes' .qrc files can't be used. Themes can ship a compiled .rcc instead");
+    }
+
+    Ok(None)
+}
+
+fn files_with_extension(dir: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.is_file()
+                        && path.extension().map_or(false, |extension| {
+                            extensions
+                                .iter()
+                                .any(|wanted| extension.eq_ignore_ascii_case(wanted))
+                        })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    files.sort();
+
+    files
+}
@@ -0,0 +1,350 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use flate2::read::GzDecoder;
+
+use crate::themes::{self, read_manifest, user_themes_dir};
+
+/// The archive has the theme's files at the top, or a single folder with them that names it.
+pub fn install(archive: &Path) -> Result<String, String> {
+    install_into(archive, &user_themes_dir())
+}
+
+fn install_into(archive: &Path, themes_dir: &Path) -> Result<String, String> {
+    fs::create_dir_all(themes_dir)
+        .map_err(|err| format!("could not create {}: {}", themes_dir.display(), err))?;
+
+    // Extracted next to where it ends up, so moving it there can't fail halfway
+    let staging_dir = themes_dir.join(format!(".installing-{}", process::id()));
+
+    let result = extract(archive, &staging_dir)
+        .map_err(|err| format!("could not extract {}: {}", archive.display(), err))
+        .and_then(|_| install_extracted(archive, &staging_dir, themes_dir));
+
+    let _ = fs::remove_dir_all(&staging_dir);
+
+    result
+}
+
+fn install_extracted(
+    archive: &Path,
+    staging_dir: &Path,
+    themes_dir: &Path,
+) -> Result<String, String> {
+    let (theme_dir, name) = find_theme_root(archive, staging_dir)?;
+
+    if name.is_empty() || name.starts_with('.') {
+        return Err(format!("\"{name}\" isn't a valid theme name"));
+    }
+
+    let manifest = read_manifest(&theme_dir)?.unwrap_or_default();
+
+    if let Some(required) = manifest.required_version()? {
+        return Err(format!("it needs krustyfy {required} or newer"));
+    }
+
+    let target = themes_dir.join(&name);
+
+    if target.exists() {
+        fs::remove_dir_all(&target)
+            .map_err(|err| format!("could not replace {}: {}", target.display(), err))?;
+    }
+
+    fs::rename(&theme_dir, &target)
+        .map_err(|err| format!("could not move it to {}: {}", target.display(), err))?;
+
+    Ok(name)
+}
+
+fn extract(archive: &Path, destination: &Path) -> io::Result<()> {
+    let file_name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        // Entries that would end up outside of the destination are skipped
+        tar::Archive::new(GzDecoder::new(File::open(archive)?)).unpack(destination)
+    } else if file_name.ends_with(".zip") {
+        let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+
+        for index in 0..zip.len() {
+            let mut entry = zip.by_index(index)?;
+
+            let path = match entry.enclosed_name() {
+                Some(path) => destination.join(path),
+                None => continue,
+            };
+
+            if entry.is_dir() {
+                fs::create_dir_all(&path)?;
+            } else {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                io::copy(&mut entry, &mut File::create(&path)?)?;
+            }
+        }
+
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only .tar.gz and .zip archives are supported",
+        ))
+    }
+}
+
+/// Where the theme's files are in the extracted archive, and what it's called.
+fn find_theme_root(archive: &Path, staging_dir: &Path) -> Result<(PathBuf, String), String> {
+    if themes::is_theme(staging_dir) {
+        let file_name = archive
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let name = [".tar.gz", ".tgz", ".zip"]
+            .iter()
+            .find_map(|extension| file_name.strip_suffix(extension))
+            .unwrap_or(&file_name)
+            .to_string();
+
+        return Ok((staging_dir.to_path_buf(), name));
+    }
+
+    let entries: Vec<PathBuf> = fs::read_dir(staging_dir)
+        .map_err(|err| err.to_string())?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+
+    match entries.as_slice() {
+        [dir] if themes::is_theme(dir) => {
+            let name = dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            Ok((dir.clone(), name))
+        }
+        _ => Err(String::from(
+            "there's no template.ui or theme.toml in it, or in a single folder in it",
+        )),
+    }
+}
+
+/// Removes a theme from the user's themes folder. The ones that come with krustyfy stay.
+pub fn remove(name: &str) -> Result<(), String> {
+    let dir = user_themes_dir().join(name);
+
+    if name.is_empty() || name.starts_with('.') || name.contains('/') || !themes::is_theme(&dir) {
+        return Err(format!(
+            "there's no \"{name}\" theme in {}",
+            user_themes_dir().display()
+        ));
+    }
+
+    fs::remove_dir_all(&dir).map_err(|err| format!("could not remove {}: {}", dir.display(), err))
+}
+
+/// Prints every theme, with whatever its theme.toml says about it.
+pub fn print_list() {
+    for name in themes::available_themes() {
+        let dir = match themes::find_theme(&name) {
+            Some(dir) => dir,
+            None => continue,
+        };
+
+        let manifest = match read_manifest(&dir) {
+            Ok(manifest) => manifest.unwrap_or_default(),
+            Err(err) => {
+                println!("{name}\n  {err}\n");
+                continue;
+            }
+        };
+
+        let mut title = name.clone();
+
+        if let Some(display_name) = &manifest.name {
+            title = format!("{display_name} ({name})");
+        }
+
+        if let Some(version) = &manifest.version {
+            title = format!("{title} {version}");
+        }
+
+        println!("{title}");
+
+        if let Some(author) = &manifest.author {
+            println!("  by {author}");
+        }
+
+        if let Some(description) = &manifest.description {
+            println!("  {description}");
+        }
+
+        if let Some(preview) = &manifest.preview {
+            println!("  preview: {}", dir.join(preview).display());
+        }
+
+        if let Some(extends) = &manifest.extends {
+            println!("  extends: {extends}");
+        }
+
+        if let Ok(Some(required)) = manifest.required_version() {
+            println!("  needs krustyfy {required} or newer");
+        }
+
+        println!("  in {}\n", dir.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    const TEMPLATE: &[u8] = b"<ui version=\"4.0\"/>";
+
+    // A file's path in the archive, and its contents
+    type Entry<'a> = (&'a str, &'a [u8]);
+
+    // A scratch folder, removed once the test is done.
+    struct TempDir {
+        path: PathBuf,
+    }
+
+    impl TempDir {
+        fn new(test: &str) -> TempDir {
+            let path = env::temp_dir().join(format!(
+                "{}-{}-{}",
+                env!("CARGO_PKG_NAME"),
+                test,
+                process::id()
+            ));
+
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).expect("could not create the temporary folder");
+
+            TempDir { path }
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    // The tar crate won't write `..` itself, so the name goes straight into the header.
+    fn write_tar_gz(path: &Path, entries: &[Entry<'_>]) {
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(path).expect("could not create the archive"),
+            Compression::default(),
+        ));
+
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_old();
+
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+
+            builder
+                .append(&header, *contents)
+                .expect("could not add to the archive");
+        }
+
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .expect("could not finish the archive");
+    }
+
+    fn write_zip(path: &Path, entries: &[Entry<'_>]) {
+        let mut zip =
+            zip::ZipWriter::new(File::create(path).expect("could not create the archive"));
+
+        for (name, contents) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .expect("could not add to the archive");
+            zip.write_all(contents)
+                .expect("could not add to the archive");
+        }
+
+        zip.finish().expect("could not finish the archive");
+    }
+
+    fn check_installs_safely(test: &str, archive_name: &str, write: fn(&Path, &[Entry<'_>])) {
+        let temp = TempDir::new(test);
+        let themes_dir = temp.path.join("data").join("themes");
+        let archive = temp.path.join(archive_name);
+
+        write(
+            &archive,
+            &[
+                ("neon/template.ui", TEMPLATE),
+                ("../escaped", b"outside the staging folder"),
+                ("neon/../../../escaped", b"outside the themes folder"),
+            ],
+        );
+
+        assert_eq!(
+            install_into(&archive, &themes_dir),
+            Ok(String::from("neon"))
+        );
+        assert!(themes_dir.join("neon").join("template.ui").is_file());
+        assert!(!themes_dir.join("escaped").exists());
+        assert!(!temp.path.join("data").join("escaped").exists());
+        assert!(!temp.path.join("escaped").exists());
+    }
+
+    #[test]
+    fn tar_entries_cant_escape() {
+        check_installs_safely("tar-traversal", "neon.tar.gz", write_tar_gz);
+    }
+
+    #[test]
+    fn zip_entries_cant_escape() {
+        check_installs_safely("zip-traversal", "neon.zip", write_zip);
+    }
+
+    #[test]
+    fn themes_at_the_top_are_named_after_the_archive() {
+        let temp = TempDir::new("top-level");
+        let themes_dir = temp.path.join("themes");
+        let archive = temp.path.join("Flat.zip");
+
+        write_zip(&archive, &[("template.ui", TEMPLATE)]);
+
+        assert_eq!(
+            install_into(&archive, &themes_dir),
+            Ok(String::from("Flat"))
+        );
+        assert!(themes_dir.join("Flat").join("template.ui").is_file());
+    }
+
+    #[test]
+    fn archives_without_a_theme_are_rejected() {
+        let temp = TempDir::new("no-theme");
+        let themes_dir = temp.path.join("themes");
+        let archive = temp.path.join("notes.tar.gz");
+
+        write_tar_gz(&archive, &[("notes/readme.txt", b"not a theme")]);
+
+        assert!(install_into(&archive, &themes_dir).is_err());
+        assert!(fs::read_dir(&themes_dir)
+            .expect("the themes folder is created")
+            .next()
+            .is_none());
+    }
+}
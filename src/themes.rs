@@ -0,0 +1,774 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use cpp_core::{CppDeletable, Ptr, Ref, StaticUpcast};
+use lazy_static::lazy_static;
+use qt_core::{
+    qs, slot, QBox, QFileSystemWatcher, QObject, QPtr, QString, QTimer, SlotNoArgs, SlotOfQString,
+};
+use qt_gui::QColor;
+use qt_widgets::{QFrame, QLabel, QPushButton, QWidget};
+use semver::Version;
+use serde::Deserialize;
+
+use crate::color_scheme::ColorScheme;
+use crate::image_handler::{Clip, Fit};
+use crate::settings::SETTINGS;
+use crate::template::{self, AnimationStyle, Template, TEMPLATE_FILE};
+use crate::tray_menu::show_message;
+
+pub const DEFAULT_THEME: &str = "default";
+
+/// A theme's theme.toml. With `extends`, it only has what it changes from its parent.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Manifest {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub preview: Option<String>,
+    pub min_krustyfy_version: Option<String>,
+    pub extends: Option<String>,
+    pub properties: BTreeMap<String, toml::Value>,
+    pub stylesheets: Vec<String>,
+    // Widget names, each replaced by the top widget of a .ui file
+    pub widgets: BTreeMap<String, String>,
+    pub light: SchemeOverrides,
+    pub dark: SchemeOverrides,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct SchemeOverrides {
+    pub properties: BTreeMap<String, toml::Value>,
+    pub stylesheets: Vec<String>,
+}
+
+impl SchemeOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty() && self.stylesheets.is_empty()
+    }
+}
+
+impl Manifest {
+    pub fn scheme(&self, scheme: ColorScheme) -> &SchemeOverrides {
+        match scheme {
+            ColorScheme::Light => &self.light,
+            ColorScheme::Dark => &self.dark,
+        }
+    }
+
+    /// The krustyfy version the theme needs, if it's newer than this one.
+    pub fn required_version(&self) -> Result<Option<String>, String> {
+        let required = match &self.min_krustyfy_version {
+            Some(required) => required,
+            None => return Ok(None),
+        };
+
+        let required_version = Version::parse(required).map_err(|err| {
+            format!("min_krustyfy_version \"{required}\" isn't a valid version: {err}")
+        })?;
+
+        let current_version =
+            Version::parse(env!("CARGO_PKG_VERSION")).expect("the package version is valid");
+
+        if required_version > current_version {
+            Ok(Some(required.clone()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+pub fn read_manifest(dir: &Path) -> Result<Option<Manifest>, String> {
+    let path = dir.join("theme.toml");
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+
+    toml::from_str(&contents)
+        .map(Some)
+        .map_err(|err| format!("could not load {}: {}", path.display(), err))
+}
+
+/// The theme in `dir` followed by every theme it extends, closest first.
+pub fn theme_chain(dir: &Path) -> Result<Vec<(PathBuf, Manifest)>, String> {
+    theme_chain_with(dir, find_theme)
+}
+
+fn theme_chain_with(
+    dir: &Path,
+    find_theme: impl Fn(&str) -> Option<PathBuf>,
+) -> Result<Vec<(PathBuf, Manifest)>, String> {
+    let mut chain: Vec<(PathBuf, Manifest)> = Vec::new();
+    let mut dir = dir.to_path_buf();
+
+    loop {
+        let manifest = read_manifest(&dir)?.unwrap_or_default();
+        let parent = manifest.extends.clone();
+
+        chain.push((dir, manifest));
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return Ok(chain),
+        };
+
+        dir = find_theme(&parent)
+            .ok_or_else(|| format!("it extends \"{parent}\", which isn't installed"))?;
+
+        if chain.iter().any(|(theme, _)| *theme == dir) {
+            let names: Vec<String> = chain
+                .iter()
+                .map(|(theme, _)| theme_name(theme))
+                .chain([theme_name(&dir)])
+                .collect();
+
+            return Err(format!("it extends itself: {}", names.join(" -> ")));
+        }
+    }
+}
+
+fn theme_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+pub fn is_theme(dir: &Path) -> bool {
+    dir.join("template.ui").is_file() || dir.join("theme.toml").is_file()
+}
+
+pub fn user_themes_dir() -> PathBuf {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(data_home) if !data_home.is_empty() => PathBuf::from(data_home),
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/share"),
+    };
+
+    data_home.join(env!("CARGO_PKG_NAME")).join("themes")
+}
+
+/// The user's, then the system's, then the ones next to the executable.
+pub fn theme_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![user_themes_dir()];
+
+    let data_dirs = match env::var("XDG_DATA_DIRS") {
+        Ok(data_dirs) if !data_dirs.is_empty() => data_dirs,
+        _ => String::from("/usr/local/share:/usr/share"),
+    };
+
+    for data_dir in data_dirs.split(':').filter(|data_dir| !data_dir.is_empty()) {
+        dirs.push(
+            Path::new(data_dir)
+                .join(env!("CARGO_PKG_NAME"))
+                .join("themes"),
+        );
+    }
+
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        dirs.push(exe_dir.join("res").join("themes"));
+    }
+
+    dirs
+}
+
+pub fn find_theme(name: &str) -> Option<PathBuf> {
+    theme_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|theme| is_theme(theme))
+}
+
+pub fn display_name(name: &str) -> String {
+    find_theme(name)
+        .and_then(|dir| read_manifest(&dir).ok().flatten())
+        .and_then(|manifest| manifest.name)
+        .unwrap_or_else(|| name.to_string())
+}
+
+pub fn available_themes() -> Vec<String> {
+    let mut themes = BTreeSet::new();
+
+    for dir in theme_dirs() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            if is_theme(&entry.path()) {
+                themes.insert(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+
+    themes.into_iter().collect()
+}
+
+/// It can't be used if there are any errors.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+const REQUIRED_WIDGETS: [&str; 4] = ["notification", "overlay", "pushButton", "notificationFrame"];
+
+const OPTIONAL_LABELS: [&str; 9] = [
+    "iconLabel",
+    "appNameLabel",
+    "imageLabel",
+    "titleLabel",
+    "bodyLabel",
+    "groupCountLabel",
+    "repeatCountLabel",
+    "overflowLabel",
+    "timeLabel",
+];
+
+const NUMBER_PROPERTIES: [&str; 8] = [
+    "defaultOpacity",
+    "hoveredOpacity",
+    "defaultBlur",
+    "hoveredBlur",
+    "endBlur",
+    "notificationDuration",
+    "spawnDuration",
+    "disappearDuration",
+];
+
+// They have a default, for themes that don't set them
+const OPTIONAL_NUMBER_PROPERTIES: [&str; 10] = [
+    "focusedOpacity",
+    "hoverDuration",
+    "moveDuration",
+    "defaultShadowBlur",
+    "focusedShadowBlur",
+    "shadowOffset",
+    "textShadowBlur",
+    "iconSize",
+    "iconRoundness",
+    "imageRoundness",
+];
+
+const EASING_PROPERTIES: [&str; 3] = ["entryEasing", "exitEasing", "moveEasing"];
+
+const ANIMATION_PROPERTIES: [&str; 2] = ["entryAnimation", "exitAnimation"];
+
+const FIT_PROPERTIES: [&str; 2] = ["iconFit", "imageFit"];
+
+const CLIP_PROPERTIES: [&str; 2] = ["iconClip", "imageClip"];
+
+const COLOR_PROPERTIES: [&str; 3] = [
+    "defaultShadowColor",
+    "focusedShadowColor",
+    "textShadowColor",
+];
+
+lazy_static! {
+    static ref CHECKED: Mutex<HashMap<String, bool>> = Mutex::new(HashMap::new());
+}
+
+pub unsafe fn validate(dir: &Path) -> Report {
+    let mut report = Report::default();
+
+    let chain = match theme_chain(dir) {
+        Ok(chain) => chain,
+        Err(err) => {
+            report.errors.push(err);
+            return report;
+        }
+    };
+
+    for (_, manifest) in &chain {
+        match manifest.required_version() {
+            Ok(Some(required)) => report
+                .errors
+                .push(format!("it needs krustyfy {required} or newer")),
+            Ok(None) => (),
+            Err(err) => report.errors.push(err),
+        }
+    }
+
+    if !report.errors.is_empty() {
+        return report;
+    }
+
+    // A theme that changes something for a color scheme is checked with each of them
+    let schemes = if chain
+        .iter()
+        .any(|(_, manifest)| !manifest.light.is_empty() || !manifest.dark.is_empty())
+    {
+        vec![Some(ColorScheme::Light), Some(ColorScheme::Dark)]
+    } else {
+        vec![None]
+    };
+
+    // Every variant has to work, since any of them can be picked at any time
+    for file in template::variant_files(&chain) {
+        for scheme in &schemes {
+            let prefix = match (file == TEMPLATE_FILE, scheme) {
+                (true, None) => String::new(),
+                (true, Some(scheme)) => format!("{}: ", scheme.name()),
+                (false, None) => format!("{file}: "),
+                (false, Some(scheme)) => format!("{file} ({}): ", scheme.name()),
+            };
+
+            let scheme = scheme.unwrap_or(ColorScheme::Dark);
+
+            let template = match Template::load(dir, &[file.clone()], scheme).and_then(|template| {
+                template
+                    .build()
+                    .map_err(|_| format!("{file} couldn't be loaded"))
+            }) {
+                Ok(template) => template,
+                Err(err) => {
+                    report.errors.push(format!("{prefix}{err}"));
+                    continue;
+                }
+            };
+
+            check_template(&template, &prefix, &mut report);
+
+            template.close();
+            template.delete();
+        }
+    }
+
+    report
+}
+
+unsafe fn check_template(template: &QPtr<QWidget>, prefix: &str, report: &mut Report) {
+    for name in REQUIRED_WIDGETS {
+        let widget: Result<QPtr<QWidget>, _> = template.find_child(name);
+
+        if widget.is_err() {
+            report
+                .errors
+                .push(format!("{prefix}the \"{name}\" widget is missing"));
+        }
+    }
+
+    let button: Result<QPtr<QWidget>, _> = template.find_child("pushButton");
+    let typed_button: Result<QPtr<QPushButton>, _> = template.find_child("pushButton");
+
+    if button.is_ok() && typed_button.is_err() {
+        report
+            .errors
+            .push(format!("{prefix}\"pushButton\" has to be a QPushButton"));
+    }
+
+    let frame: Result<QPtr<QWidget>, _> = template.find_child("notificationFrame");
+    let typed_frame: Result<QPtr<QFrame>, _> = template.find_child("notificationFrame");
+
+    if frame.is_ok() && typed_frame.is_err() {
+        report
+            .errors
+            .push(format!("{prefix}\"notificationFrame\" has to be a QFrame"));
+    }
+
+    for name in OPTIONAL_LABELS {
+        let widget: Result<QPtr<QWidget>, _> = template.find_child(name);
+        let label: Result<QPtr<QLabel>, _> = template.find_child(name);
+
+        if widget.is_ok() && label.is_err() {
+            report.warnings.push(format!(
+                "{prefix}\"{name}\" isn't a QLabel, so it won't be used"
+            ));
+        }
+    }
+
+    for (name, required) in NUMBER_PROPERTIES
+        .iter()
+        .map(|name| (name, true))
+        .chain(OPTIONAL_NUMBER_PROPERTIES.iter().map(|name| (name, false)))
+    {
+        let value = template.property(CString::new(*name).unwrap_or_default().as_ptr());
+
+        if !value.is_valid() && !required {
+            continue;
+        }
+
+        if !value.is_valid() {
+            report
+                .errors
+                .push(format!("{prefix}the {name} property is missing"));
+            continue;
+        }
+
+        let mut ok = false;
+        value.to_double_1a(&mut ok);
+
+        if !ok {
+            report.errors.push(format!(
+                "{prefix}the {name} property should be a number, not \"{}\"",
+                value.to_string().to_std_string()
+            ));
+        }
+    }
+
+    for name in COLOR_PROPERTIES {
+        let value = template.property(CString::new(name).unwrap_or_default().as_ptr());
+
+        if !value.is_valid() {
+            report
+                .errors
+                .push(format!("{prefix}the {name} property is missing"));
+        } else if !QColor::from_q_string(&value.to_string()).is_valid() {
+            report.errors.push(format!(
+                "{prefix}the {name} property should be a color, not \"{}\"",
+                value.to_string().to_std_string()
+            ));
+        }
+    }
+
+    for name in EASING_PROPERTIES {
+        let value = template.property(CString::new(name).unwrap_or_default().as_ptr());
+
+        if value.is_valid() && template::easing_curve(&value.to_string().to_std_string()).is_none()
+        {
+            report.errors.push(format!(
+                "{prefix}the {name} property should be a QEasingCurve type like OutCurve, not \"{}\"",
+                value.to_string().to_std_string()
+            ));
+        }
+    }
+
+    for name in ANIMATION_PROPERTIES {
+        let value = template.property(CString::new(name).unwrap_or_default().as_ptr());
+
+        if value.is_valid()
+            && AnimationStyle::from_name(&value.to_string().to_std_string()).is_none()
+        {
+            report.errors.push(format!(
+                "{prefix}the {name} property should be slideTop, slideLeft, slideRight, slideBottom, fade, scale or none, not \"{}\"",
+                value.to_string().to_std_string()
+            ));
+        }
+    }
+
+    for name in FIT_PROPERTIES {
+        let value = template.property(CString::new(name).unwrap_or_default().as_ptr());
+
+        if value.is_valid() && Fit::from_name(&value.to_string().to_std_string()).is_none() {
+            report.errors.push(format!(
+                "{prefix}the {name} property should be stretch, contain, cover or original, not \"{}\"",
+                value.to_string().to_std_string()
+            ));
+        }
+    }
+
+    for name in CLIP_PROPERTIES {
+        let value = template.property(CString::new(name).unwrap_or_default().as_ptr());
+
+        if value.is_valid() && Clip::from_name(&value.to_string().to_std_string()).is_none() {
+            report.errors.push(format!(
+                "{prefix}the {name} property should be rounded, circle or none, not \"{}\"",
+                value.to_string().to_std_string()
+            ));
+        }
+    }
+}
+
+/// The first time a theme is checked, whatever is wrong with it is printed and shown from the tray.
+pub unsafe fn check_theme(name: &str) -> bool {
+    if let Some(usable) = CHECKED
+        .lock()
+        .expect("could not acquire checked themes lock")
+        .get(name)
+    {
+        return *usable;
+    }
+
+    let report = match find_theme(name) {
+        Some(dir) => validate(&dir),
+        None => Report {
+            errors: vec![String::from("it isn't in any of the theme folders")],
+            warnings: Vec::new(),
+        },
+    };
+
+    for warning in &report.warnings {
+        eprintln!("Theme \"{name}\": {warning}");
+    }
+
+    for error in &report.errors {
+        eprintln!("Theme \"{name}\": {error}");
+    }
+
+    let usable = report.errors.is_empty();
+
+    if !usable {
+        show_message(
+            &format!("The \"{name}\" theme can't be used"),
+            &format!(
+                "{}.\nThe default theme is used instead.",
+                report.errors.join(".\n")
+            ),
+        );
+    }
+
+    CHECKED
+        .lock()
+        .expect("could not acquire checked themes lock")
+        .insert(name.to_string(), usable);
+
+    usable
+}
+
+pub fn forget_checked(name: &str) {
+    CHECKED
+        .lock()
+        .expect("could not acquire checked themes lock")
+        .remove(name);
+}
+
+// --check-theme <dir>
+pub unsafe fn print_report(dir: &Path) -> i32 {
+    println!("Checking the theme in {}", dir.display());
+
+    let report = validate(dir);
+
+    for warning in &report.warnings {
+        println!("  warning: {warning}");
+    }
+
+    for error in &report.errors {
+        println!("  error: {error}");
+    }
+
+    if report.errors.is_empty() {
+        println!("The theme is fine.");
+        0
+    } else {
+        println!("The theme can't be used: {} error(s).", report.errors.len());
+        1
+    }
+}
+
+/// Calls `on_change` whenever any file of the active theme changes.
+pub struct ThemeWatcher {
+    watcher: QBox<QFileSystemWatcher>,
+    // Editors write files in several steps, so changes are only acted on once they settle
+    timer: QBox<QTimer>,
+    theme: RefCell<String>,
+    changed: Cell<bool>,
+    on_change: Box<dyn Fn(&str)>,
+}
+
+impl StaticUpcast<QObject> for ThemeWatcher {
+    unsafe fn static_upcast(ptr: Ptr<Self>) -> Ptr<QObject> {
+        ptr.watcher.as_ptr().static_upcast()
+    }
+}
+
+impl ThemeWatcher {
+    pub fn new(on_change: impl Fn(&str) + 'static) -> Rc<ThemeWatcher> {
+        unsafe {
+            let watcher = QFileSystemWatcher::new_0a();
+
+            let timer = QTimer::new_0a();
+            timer.set_interval(500);
+
+            let this = Rc::new(Self {
+                watcher,
+                timer,
+                theme: RefCell::new(SETTINGS.theme.name.to_string()),
+                changed: Cell::new(false),
+                on_change: Box::new(on_change),
+            });
+
+            this.init();
+
+            this
+        }
+    }
+
+    unsafe fn init(self: &Rc<Self>) {
+        self.watch();
+
+        self.watcher.file_changed().connect(&self.slot_on_changed());
+        self.watcher
+            .directory_changed()
+            .connect(&self.slot_on_changed());
+
+        self.timer.timeout().connect(&self.slot_on_tick());
+        self.timer.start_0a();
+    }
+
+    // The theme's directory and its parents', with every file and directory under them
+    unsafe fn watch(self: &Rc<Self>) {
+        let files = self.watcher.files();
+
+        if !files.is_empty() {
+            self.watcher.remove_paths(&files);
+        }
+
+        let directories = self.watcher.directories();
+
+        if !directories.is_empty() {
+            self.watcher.remove_paths(&directories);
+        }
+
+        let dir = match find_theme(&self.theme.borrow()) {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        // Changes to the themes it extends show up in it too
+        let mut pending = match theme_chain(&dir) {
+            Ok(chain) => chain.into_iter().map(|(theme, _)| theme).collect(),
+            Err(_) => vec![dir],
+        };
+
+        while let Some(path) = pending.pop() {
+            self.watcher.add_path(&qs(path.to_string_lossy()));
+
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+    }
+
+    #[slot(SlotOfQString)]
+    unsafe fn on_changed(self: &Rc<Self>, _path: Ref<QString>) {
+        self.changed.set(true);
+    }
+
+    #[slot(SlotNoArgs)]
+    unsafe fn on_tick(self: &Rc<Self>) {
+        // Switching themes only affects new notifications, there's nothing to reload
+        if *self.theme.borrow() != SETTINGS.theme.name {
+            let previous = self.theme.replace(SETTINGS.theme.name.to_string());
+
+            // Its fonts and resources are registered again if it's used again
+            template::retire_template(&previous);
+
+            self.changed.set(false);
+            self.watch();
+
+            return;
+        }
+
+        if !self.changed.replace(false) {
+            return;
+        }
+
+        // Files that were replaced or added have to be watched again
+        self.watch();
+
+        let theme = self.theme.borrow().clone();
+
+        (self.on_change)(&theme);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Theme folders with the given theme.toml contents, removed once the test is done.
+    struct Themes {
+        dir: PathBuf,
+    }
+
+    impl Themes {
+        fn new(test: &str, themes: &[(&str, &str)]) -> Themes {
+            let dir = env::temp_dir().join(format!(
+                "{}-{}-{}",
+                env!("CARGO_PKG_NAME"),
+                test,
+                std::process::id()
+            ));
+
+            for (name, manifest) in themes {
+                fs::create_dir_all(dir.join(name)).expect("could not create the theme");
+                fs::write(dir.join(name).join("theme.toml"), manifest)
+                    .expect("could not write the manifest");
+            }
+
+            Themes { dir }
+        }
+
+        fn chain(&self, name: &str) -> Result<Vec<String>, String> {
+            let chain = theme_chain_with(&self.dir.join(name), |name| {
+                Some(self.dir.join(name)).filter(|theme| is_theme(theme))
+            })?;
+
+            Ok(chain.iter().map(|(theme, _)| theme_name(theme)).collect())
+        }
+    }
+
+    impl Drop for Themes {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn follows_extends() {
+        let themes = Themes::new(
+            "follows-extends",
+            &[
+                ("base", ""),
+                ("dark", "extends = \"base\""),
+                ("darker", "extends = \"dark\""),
+            ],
+        );
+
+        assert_eq!(themes.chain("darker").unwrap(), ["darker", "dark", "base"]);
+        assert_eq!(themes.chain("base").unwrap(), ["base"]);
+    }
+
+    #[test]
+    fn missing_parent() {
+        let themes = Themes::new("missing-parent", &[("orphan", "extends = \"gone\"")]);
+
+        assert_eq!(
+            themes.chain("orphan").unwrap_err(),
+            "it extends \"gone\", which isn't installed"
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        let themes = Themes::new(
+            "cycles",
+            &[
+                ("first", "extends = \"second\""),
+                ("second", "extends = \"first\""),
+                ("selfish", "extends = \"selfish\""),
+            ],
+        );
+
+        assert_eq!(
+            themes.chain("first").unwrap_err(),
+            "it extends itself: first -> second -> first"
+        );
+        assert_eq!(
+            themes.chain("selfish").unwrap_err(),
+            "it extends itself: selfish -> selfish"
+        );
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let themes = Themes::new("invalid-manifest", &[("broken", "extends = 3")]);
+
+        assert!(themes.chain("broken").is_err());
+    }
+}
@@ -1,12 +1,33 @@
 use cpp_core::CppBox;
-use qt_core::q_dir::Filter;
-use qt_core::{qs, QBox, QDir, QDirIterator, QString, QVariant};
+use qt_core::{qs, QBox, QPtr, QString, QVariant, SlotNoArgs};
 use qt_gui::{QGuiApplication, QIcon};
+use qt_widgets::q_system_tray_icon::MessageIcon;
 use qt_widgets::{QActionGroup, QApplication, QMenu, QSystemTrayIcon, SlotOfQAction};
 
 use crate::settings::Setting;
+use crate::themes;
 use crate::SETTINGS;
 
+static mut TRAY_ICON: Option<QPtr<QSystemTrayIcon>> = None;
+
+/// Messages that came in before the tray icon existed
+static mut PENDING_MESSAGES: Vec<(String, String)> = Vec::new();
+
+/// Shows a warning bubble from the tray icon, or as soon as there is one.
+pub unsafe fn show_message(title: &str, message: &str) {
+    match &TRAY_ICON {
+        Some(tray_icon) if !tray_icon.is_null() => {
+            tray_icon.show_message_q_string_q_string_message_icon_int(
+                &qs(title),
+                &qs(message),
+                MessageIcon::Warning,
+                10000,
+            );
+        }
+        _ => PENDING_MESSAGES.push((title.to_string(), message.to_string())),
+    }
+}
+
 pub struct MenuItem {
     label: CppBox<QString>,
     value: CppBox<QVariant>,
@@ -15,17 +36,10 @@ pub struct MenuItem {
 pub fn get_available_themes() -> Vec<MenuItem> {
     let mut values: Vec<MenuItem> = Vec::new();
     unsafe {
-        let theme_directories = QDirIterator::from_q_string_q_flags_filter(
-            &qs("./res/themes"),
-            Filter::AllDirs | Filter::NoDotAndDotDot,
-        );
-
-        while theme_directories.has_next() {
-            let theme = QDir::new_1a(&theme_directories.next());
-
+        for theme in themes::available_themes() {
             values.push(MenuItem {
-                label: theme.dir_name(),
-                value: QVariant::from_q_string(theme.dir_name().as_ref()),
+                label: qs(themes::display_name(&theme)),
+                value: QVariant::from_q_string(&qs(&theme)),
             })
         }
     }
@@ -61,6 +75,12 @@ pub unsafe fn generate_tray() -> (QBox<QSystemTrayIcon>, QBox<QMenu>) {
 
     tray_icon.show();
 
+    TRAY_ICON = Some(QPtr::new(tray_icon.as_ptr()));
+
+    for (title, message) in PENDING_MESSAGES.drain(..) {
+        show_message(&title, &message);
+    }
+
     let tray_menu = QMenu::new();
 
     let theme_menu = tray_menu.add_menu_q_string(&qs("Themes"));
@@ -71,15 +91,13 @@ pub unsafe fn generate_tray() -> (QBox<QSystemTrayIcon>, QBox<QMenu>) {
     let theme_directories = get_available_themes();
 
     for theme in theme_directories {
-        let theme = theme.label;
-
-        let theme_action = theme_menu.add_action_q_string(&theme);
+        let theme_action = theme_menu.add_action_q_string(&theme.label);
 
         theme_action.set_object_name(&qs("set_theme"));
         theme_action.set_checkable(true);
-        theme_action.set_data(&QVariant::from_q_string(&theme));
+        theme_action.set_data(&theme.value);
 
-        if SETTINGS.theme.name == theme.to_std_string() {
+        if SETTINGS.theme.name == theme.value.to_string().to_std_string() {
             theme_action.set_checked(true);
         }
 
@@ -112,6 +130,7 @@ pub unsafe fn generate_tray() -> (QBox<QSystemTrayIcon>, QBox<QMenu>) {
     let do_not_disturb_action = tray_menu.add_action_q_string(&qs("Do not disturb"));
     do_not_disturb_action.set_object_name(&qs("do_not_disturb_action"));
     do_not_disturb_action.set_checkable(true);
+    do_not_disturb_action.set_checked(SETTINGS.do_not_disturb.value);
 
     let quit_action = tray_menu.add_action_q_string(&qs("Quit"));
     quit_action.set_object_name(&qs("quit_action"));
@@ -122,6 +141,40 @@ pub unsafe fn generate_tray() -> (QBox<QSystemTrayIcon>, QBox<QMenu>) {
 
     let tray_icon_ptr = tray_icon.as_ptr();
 
+    // config.toml can change under us, so the checks are brought up to date every time it opens
+    let theme_action_group_ptr = theme_action_group.as_ptr();
+    let screens_action_group_ptr = screens_action_group.as_ptr();
+    let do_not_disturb_action_ptr = do_not_disturb_action.as_ptr();
+
+    tray_menu
+        .about_to_show()
+        .connect(&SlotNoArgs::new(&tray_menu, move || {
+            let theme_actions = theme_action_group_ptr.actions();
+
+            for i in 0..theme_actions.length() {
+                let theme_action = theme_actions.value_1a(i);
+
+                theme_action.set_checked(
+                    theme_action.data().to_string().to_std_string() == SETTINGS.theme.name,
+                );
+            }
+
+            let screen_actions = screens_action_group_ptr.actions();
+
+            for i in 0..screen_actions.length() {
+                let screen_action = screen_actions.value_1a(i);
+
+                // The first one is the primary screen
+                screen_action.set_checked(if SETTINGS.screen.id == -1 {
+                    i == 0
+                } else {
+                    screen_action.text().to_std_string() == SETTINGS.screen.name
+                });
+            }
+
+            do_not_disturb_action_ptr.set_checked(SETTINGS.do_not_disturb.value);
+        }));
+
     tray_menu
         .triggered()
         .connect(&SlotOfQAction::new(&tray_menu, move |action| {
@@ -134,6 +187,7 @@ pub unsafe fn generate_tray() -> (QBox<QSystemTrayIcon>, QBox<QMenu>) {
                 settings
                     .do_not_disturb
                     .set(QVariant::from_bool(action.is_checked()));
+                settings.do_not_disturb.save();
             }
 
             if action.object_name().to_std_string() == "set_theme".to_string() {